@@ -0,0 +1,86 @@
+use parking_lot::RwLock;
+use std::{
+    fs::read_to_string,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use super::{Cidr, Config, Invalid, Result};
+
+/// Ranges of addresses that may or may not connect
+#[derive(Clone, Debug, Default)]
+pub struct AccessList {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    /// Read a file of rules, one `allow CIDR` or `deny CIDR` per line.
+    fn read(file: &Path) -> Result<Self> {
+        let mut list = Self::default();
+        for l in read_to_string(file)?
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let (rule, cidr) = l.split_once(char::is_whitespace).unwrap_or((l, ""));
+            let cidr = cidr.trim().parse().map_err(Invalid::Access)?;
+            match rule {
+                "allow" => list.allow.push(cidr),
+                "deny" => list.deny.push(cidr),
+                _ => return Err(Invalid::Access(format!("{:?} is not a rule", l)).into()),
+            }
+        }
+        Ok(list)
+    }
+
+    /// Combine the rules of both lists.
+    fn extend(&mut self, other: Self) {
+        self.allow.extend(other.allow);
+        self.deny.extend(other.deny);
+    }
+
+    /// Determinant for whether the address may connect, where denials take precedence
+    /// and an empty allow list allows everyone.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|c| c.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip)))
+    }
+}
+
+/// The access list of every listener, which can be reloaded from its file
+pub struct Access {
+    flags: AccessList,
+    file: Option<PathBuf>,
+    list: RwLock<AccessList>,
+}
+
+impl Access {
+    pub fn new(cfg: &Config) -> Result<Self> {
+        let access = Self {
+            flags: AccessList {
+                allow: cfg.allowed().to_vec(),
+                deny: cfg.denied().to_vec(),
+            },
+            file: cfg.access_file().map(Path::to_path_buf),
+            list: RwLock::default(),
+        };
+        access.reload()?;
+        Ok(access)
+    }
+
+    /// Replace the rules with those of the flags and the current contents of the file.
+    pub fn reload(&self) -> Result {
+        let mut list = self.flags.clone();
+        if let Some(file) = &self.file {
+            list.extend(AccessList::read(file)?);
+        }
+        *self.list.write() = list;
+        Ok(())
+    }
+
+    /// Determinant for whether the address may connect.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        self.list.read().permits(ip)
+    }
+}
@@ -0,0 +1,116 @@
+use clap::ValueEnum;
+
+/// A built-in set of clients that are allowed to watch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// `curl`
+    Curl,
+    /// `wget -qO-`
+    Wget,
+    /// `http --stream`
+    Httpie,
+    /// `Invoke-WebRequest` and `curl.exe` from PowerShell
+    Powershell,
+    /// Web browsers, which are served the HTML player
+    Browser,
+    /// Any client whatsoever
+    Any,
+}
+
+impl Profile {
+    /// The products of each `User-Agent` the profile allows, where browsers are told apart by their engine,
+    /// as other clients such as PowerShell claim to be `Mozilla` as well.
+    const fn products(&self) -> &'static [&'static str] {
+        match self {
+            Self::Curl => &["curl"],
+            Self::Wget => &["wget"],
+            Self::Httpie => &["httpie"],
+            Self::Powershell => &["powershell", "windowspowershell"],
+            Self::Browser => &["applewebkit", "gecko"],
+            Self::Any => &[""],
+        }
+    }
+}
+
+/// Determinant for whether `product` names any product of the `User-Agent` (e.g. `curl` of `curl/8.5.0`),
+/// ignoring case along with the comments between parentheses.
+fn has_product(agent: &[u8], product: &str) -> bool {
+    // comments may nest, and are blanked out so that they still separate the products around them
+    let mut depth = 0usize;
+    let uncommented = agent
+        .iter()
+        .map(|&b| {
+            let commented = match b {
+                b'(' => {
+                    depth += 1;
+                    true
+                }
+                b')' => {
+                    depth = depth.saturating_sub(1);
+                    true
+                }
+                _ => depth > 0,
+            };
+            if commented {
+                b' '
+            } else {
+                b
+            }
+        })
+        .collect::<Vec<_>>();
+
+    product.is_empty()
+        || uncommented
+            .split(u8::is_ascii_whitespace)
+            .filter_map(|token| token.split(|&b| b == b'/').next())
+            .any(|name| name.eq_ignore_ascii_case(product.as_bytes()))
+}
+
+/// Determinant for whether `pattern` occurs anywhere within `value`, ignoring case.
+fn matches(value: &[u8], pattern: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    pattern.is_empty()
+        || value
+            .windows(pattern.len())
+            .any(|w| w.eq_ignore_ascii_case(pattern))
+}
+
+/// Which `User-Agent` and `Accept` headers are allowed to receive the stream
+#[derive(Debug)]
+pub struct AgentPolicy {
+    products: Vec<&'static str>,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    accept: Vec<String>,
+}
+
+impl AgentPolicy {
+    pub fn new(profiles: &[Profile], allow: &[String], deny: &[String], accept: &[String]) -> Self {
+        Self {
+            products: profiles
+                .iter()
+                .flat_map(Profile::products)
+                .copied()
+                .collect(),
+            allow: allow.to_vec(),
+            deny: deny.to_vec(),
+            accept: accept.to_vec(),
+        }
+    }
+
+    /// Determinant for whether the `User-Agent` is allowed, where denials take precedence.
+    pub fn allows_agent(&self, agent: &[u8]) -> bool {
+        !self.deny.iter().any(|p| matches(agent, p))
+            && (self.products.iter().any(|p| has_product(agent, p))
+                || self.allow.iter().any(|p| matches(agent, p)))
+    }
+
+    /// Determinant for whether any of the media ranges of the `Accept` header are allowed.
+    pub fn allows_accept(&self, accept: &[u8]) -> bool {
+        String::from_utf8_lossy(accept)
+            .split(',')
+            .filter_map(|range| range.split(';').next())
+            .map(str::trim)
+            .any(|range| self.accept.iter().any(|a| a.eq_ignore_ascii_case(range)))
+    }
+}
@@ -17,7 +17,7 @@ use std::{
 };
 use zstd::{decode_all, zstd_safe::max_c_level};
 
-use super::{donut, style, AsciiFrame, Config, Error, GifError, Result};
+use super::{donut, style, AsciiFrame, Config, Error, GifError, Result, Route};
 
 fn get_frames_count(mut opt: DecodeOptions, input: &mut BufReader<File>) -> Result<u64> {
     // adjust configuration
@@ -177,11 +177,13 @@ pub fn write_file(
     Ok(frames)
 }
 
-pub fn get_frames(cfg: &Config) -> Result<Vec<AsciiFrame>> {
+pub fn get_frames(route: &Route, cfg: &Config) -> Result<Vec<AsciiFrame>> {
+    let file_name = route.file_name(cfg.is_colored());
+
     // generate and write frames to file if they don't already exist
-    let mut frames = read_file(cfg.file_name()).or_else(|_| {
+    let mut frames = read_file(&file_name).or_else(|_| {
         // save to file, while returning original result
-        write_file(cfg.gif(), cfg.fps(), cfg.is_colored(), cfg.file_name())
+        write_file(route.gif(), cfg.fps(), cfg.is_colored(), &file_name)
     })?;
 
     // number of frames
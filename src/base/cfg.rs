@@ -1,14 +1,18 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log::Level;
+use sha1::{Digest, Sha1};
 use std::{
     env::{set_var, var},
+    fs::read_to_string,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    num::{NonZeroU32, NonZeroUsize},
     ops::Deref,
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
-use super::Result;
+use super::{AgentPolicy, Cidr, Invalid, Options, Profile, Result};
 
 /// Parse the provided path, ensuring it has a root.
 fn parse_path(s: &str) -> Result<String> {
@@ -19,6 +23,188 @@ fn parse_path(s: &str) -> Result<String> {
     Ok(p)
 }
 
+/// An animation and the URI path it is served on
+#[derive(Clone, Debug)]
+pub struct Route {
+    path: String,
+    gif: Option<PathBuf>,
+}
+
+impl Route {
+    /// URI path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Path to the GIF, or `None` for the donut.
+    pub fn gif(&self) -> Option<&Path> {
+        self.gif.as_deref()
+    }
+
+    /// Name of the file the ascii-generated frames are cached in.
+    pub fn file_name(&self, is_colored: bool) -> String {
+        // the new file stem of the ascii-generated file, told apart by the full path of the GIF,
+        // as GIFs of different directories may share the same name
+        let file_stem = self.gif().map_or_else(
+            || "donuts".to_owned(),
+            |p| {
+                let stem = p
+                    .file_stem()
+                    .map(|s| s.to_str().unwrap_or("_"))
+                    .unwrap_or("_");
+                let digest = Sha1::digest(p.as_os_str().as_encoded_bytes());
+                let hash = digest[..4]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                format!("{}-{}", stem, hash)
+            },
+        );
+
+        // '.ascii' default extension
+        let mut s = format!("{}.ascii", file_stem);
+
+        // '.asciic' extension indicates colored ascii
+        if is_colored {
+            s.push('c')
+        }
+        s
+    }
+}
+
+/// Parse a route of the form `PATH[=GIF]`.
+fn parse_route(s: &str) -> Result<Route, String> {
+    let (path, gif) = match s.split_once('=') {
+        Some((path, gif)) => (path.trim(), Some(PathBuf::from(gif.trim()))),
+        None => (s.trim(), None),
+    };
+    if path.is_empty() || gif.as_ref().is_some_and(|p| p.as_os_str().is_empty()) {
+        return Err(format!("{:?} is not of the form PATH[=GIF]", s));
+    }
+    Ok(Route {
+        path: parse_path(path).map_err(|e| e.to_string())?,
+        gif,
+    })
+}
+
+/// Parse a file of routes, one per line, resolving each GIF relative to the file.
+fn read_routes(file: &Path) -> Result<Vec<Route>> {
+    let dir = file.parent().unwrap_or(Path::new(""));
+
+    read_to_string(file)?
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let mut route = parse_route(l).map_err(Invalid::Route)?;
+            route.gif = route.gif.map(|gif| dir.join(gif));
+            Ok(route)
+        })
+        .collect()
+}
+
+/// An address to listen on, along with what it serves
+#[derive(Clone, Debug)]
+pub struct Listener {
+    addr: SocketAddr,
+    prefix: String,
+    route: Option<String>,
+}
+
+impl Listener {
+    /// Address to bind
+    pub const fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URI path every route is served under, or empty for none.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Path of the only route served, at the prefix itself, or `None` for every route.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+}
+
+/// Parse a listener of the form `ADDR:PORT[/PREFIX][=PATH]`.
+fn parse_listener(s: &str) -> Result<Listener, String> {
+    let (rest, route) = match s.split_once('=') {
+        Some((rest, path)) => (
+            rest,
+            Some(parse_path(path.trim()).map_err(|e| e.to_string())?),
+        ),
+        None => (s, None),
+    };
+    let (addr, prefix) = match rest.split_once('/') {
+        Some((addr, prefix)) if !prefix.trim_matches('/').is_empty() => {
+            (addr, format!("/{}", prefix.trim_matches('/')))
+        }
+        Some((addr, _)) => (addr, String::new()),
+        None => (rest, String::new()),
+    };
+    Ok(Listener {
+        addr: SocketAddr::from_str(addr.trim())
+            .map_err(|_| format!("{:?} is not of the form ADDR:PORT[/PREFIX][=PATH]", s))?,
+        prefix,
+        route,
+    })
+}
+
+/// Parse an octal file mode, such as `660`.
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8)
+        .ok()
+        .filter(|m| *m <= 0o7777)
+        .ok_or_else(|| format!("{} is not an octal file mode", s))
+}
+
+/// Parse a positive, finite number of seconds.
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs = f32::from_str(s).map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f32(secs)
+        .ok()
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("{} is not a positive number of seconds", s))
+}
+
+/// Parse a positive number per second.
+fn parse_rate(s: &str) -> Result<f64, String> {
+    f64::from_str(s)
+        .ok()
+        .filter(|r| r.is_finite() && *r > 0.0)
+        .ok_or_else(|| format!("{} is not a positive rate", s))
+}
+
+/// A listener whose connections begin with a PROXY protocol header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Proxied {
+    /// Every listener bound to the port
+    Port(u16),
+    /// The Unix domain socket
+    Unix,
+}
+
+/// Parse a listener expecting the PROXY protocol, as its `PORT` or `unix`.
+fn parse_proxied(s: &str) -> Result<Proxied, String> {
+    match s {
+        "unix" => Ok(Proxied::Unix),
+        port => u16::from_str(port)
+            .map(Proxied::Port)
+            .map_err(|_| format!("{:?} is neither a port nor `unix`", s)),
+    }
+}
+
+/// What to do with a client whose send queue keeps overflowing
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum LagPolicy {
+    /// Skip ahead to the newest frame
+    Skip,
+    /// Disconnect after too many dropped frames
+    Disconnect,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct InitConfig {
@@ -30,6 +216,30 @@ pub struct InitConfig {
     #[arg(short, long, default_value_t = 8080)]
     port: u16,
 
+    /// Address to listen on instead of `--addr` and `--port`, as ADDR:PORT[/PREFIX][=PATH] (repeatable)
+    #[arg(short, long = "listen", value_parser = parse_listener)]
+    listeners: Vec<Listener>,
+
+    /// Unix domain socket to serve on as well, e.g. for a reverse proxy
+    #[arg(long)]
+    unix_socket: Option<PathBuf>,
+
+    /// File mode of the Unix domain socket, in octal
+    #[arg(long, default_value = "660", value_parser = parse_mode)]
+    unix_socket_mode: u32,
+
+    /// User to run as once every port is bound, by name or id
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Group to run as once every port is bound, by name or id [default: the primary group of `--user`]
+    #[arg(long)]
+    group: Option<String>,
+
+    /// Confine the process to the working directory, where the frames are cached, once every port is bound
+    #[arg(long)]
+    chroot: bool,
+
     /// URI location path
     #[arg(long, default_value_t = String::from('/'), value_parser = parse_path)]
     path: String,
@@ -38,6 +248,14 @@ pub struct InitConfig {
     #[arg(short, long)]
     gif: Option<PathBuf>,
 
+    /// Animation served on its own path, as PATH[=GIF] (repeatable)
+    #[arg(short, long = "route", value_parser = parse_route)]
+    routes: Vec<Route>,
+
+    /// File of routes, one PATH[=GIF] per line
+    #[arg(long)]
+    routes_file: Option<PathBuf>,
+
     /// Custom Frames/sec
     #[arg(long)]
     fps: Option<f32>,
@@ -49,6 +267,148 @@ pub struct InitConfig {
     /// Ensure 'COLORTERM' and 'CLICOLOR_FORCE' are set
     #[arg(short, long)]
     force_colored: bool,
+
+    /// Seconds a new connection has to send its request
+    #[arg(long, default_value = "5", value_parser = parse_secs)]
+    handshake_timeout: Duration,
+
+    /// Maximum number of handshakes in progress at once
+    #[arg(long, default_value = "64")]
+    max_pending: NonZeroUsize,
+
+    /// Maximum number of concurrent streams overall
+    #[arg(long)]
+    max_streams: Option<usize>,
+
+    /// Maximum number of concurrent streams from a single address
+    #[arg(long)]
+    max_streams_per_ip: Option<usize>,
+
+    /// Connections per second allowed from a single address, on average
+    #[arg(long, value_parser = parse_rate)]
+    accept_rate: Option<f64>,
+
+    /// Connections allowed from a single address in a burst, before the accept rate applies
+    #[arg(long, default_value = "10")]
+    accept_burst: NonZeroU32,
+
+    /// Maximum number of headers of a request
+    #[arg(long, default_value = "32")]
+    max_headers: NonZeroUsize,
+
+    /// Maximum size of the head of a request, in bytes
+    #[arg(long, default_value = "8192")]
+    max_head_size: NonZeroUsize,
+
+    /// Number of frames buffered for each client
+    #[arg(long, default_value = "4")]
+    queue_size: NonZeroUsize,
+
+    /// Handling of clients that cannot keep up
+    #[arg(long, value_enum, default_value_t = LagPolicy::Skip)]
+    lag_policy: LagPolicy,
+
+    /// Dropped frames before a lagging client is disconnected
+    #[arg(long, default_value_t = 64)]
+    max_dropped: usize,
+
+    /// End each stream after the animation has played this many times
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    loops: Option<u32>,
+
+    /// End each stream after this many seconds
+    #[arg(long, value_parser = parse_secs)]
+    duration: Option<Duration>,
+
+    /// Seconds the streams have to end once the server is asked to shut down, before they are cut off
+    #[arg(long, default_value = "5", value_parser = parse_secs)]
+    shutdown_timeout: Duration,
+
+    /// Message left below the animation when the server shuts down
+    #[arg(long)]
+    goodbye: Option<String>,
+
+    /// Never compress streams, regardless of 'Accept-Encoding'
+    #[arg(long)]
+    no_compression: bool,
+
+    /// Built-in set of clients allowed to watch, matched by the products of the 'User-Agent' (repeatable)
+    #[arg(long = "agent-profile", value_enum, default_values_t = [Profile::Curl, Profile::Browser])]
+    agent_profiles: Vec<Profile>,
+
+    /// Additional 'User-Agent' allowed to watch, matched anywhere within the header (repeatable)
+    #[arg(long = "allow-agent")]
+    allowed_agents: Vec<String>,
+
+    /// 'User-Agent' refused regardless of any profile, matched anywhere within the header (repeatable)
+    #[arg(long = "deny-agent")]
+    denied_agents: Vec<String>,
+
+    /// Media range of the 'Accept' header allowed to receive the stream (repeatable)
+    #[arg(long = "accept", default_values_t = ["*/*".to_owned(), "text/*".to_owned(), "text/plain".to_owned()])]
+    accepted: Vec<String>,
+
+    /// Addresses allowed to connect, as ADDR[/PREFIX], where everyone else is refused (repeatable)
+    #[arg(long = "allow")]
+    allowed: Vec<Cidr>,
+
+    /// Addresses refused regardless of the allowed ones, as ADDR[/PREFIX] (repeatable)
+    #[arg(long = "deny")]
+    denied: Vec<Cidr>,
+
+    /// File of access rules, one `allow ADDR[/PREFIX]` or `deny ADDR[/PREFIX]` per line, reloaded on SIGHUP
+    #[arg(long)]
+    access_file: Option<PathBuf>,
+
+    /// Proxy whose PROXY protocol, 'Forwarded', 'X-Forwarded-For' and 'X-Real-IP' headers are believed, as ADDR[/PREFIX] (repeatable)
+    #[arg(long = "trusted-proxy")]
+    trusted_proxies: Vec<Cidr>,
+
+    /// Listener whose connections begin with a PROXY protocol (v1 or v2) header, as its PORT or `unix` (repeatable)
+    #[arg(long = "proxy-protocol", value_parser = parse_proxied)]
+    proxied: Vec<Proxied>,
+
+    /// Port that streams frames directly, without any HTTP (e.g. `nc`)
+    #[arg(long)]
+    raw_port: Option<u16>,
+
+    /// URI path of the animation streamed on the raw and telnet ports [default: the first route]
+    #[arg(long, value_parser = parse_path)]
+    raw_path: Option<String>,
+
+    /// Port that streams frames over telnet, sized to the client's terminal (e.g. `telnet`)
+    #[arg(long)]
+    telnet_port: Option<u16>,
+
+    /// Port that serves the streams over TLS (HTTPS)
+    #[cfg(feature = "tls")]
+    #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+    tls_port: Option<u16>,
+
+    /// PEM file of the certificate chain, reloaded on SIGHUP
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file of the private key, reloaded on SIGHUP
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+
+    /// Port that streams frames over SSH, sized to the client's terminal (e.g. `ssh -p PORT donut@HOST`)
+    #[cfg(feature = "ssh")]
+    #[arg(long)]
+    ssh_port: Option<u16>,
+
+    /// File of the SSH host key, which is generated if it doesn't exist yet
+    #[cfg(feature = "ssh")]
+    #[arg(long, default_value = "ssh_host_ed25519_key")]
+    ssh_host_key: PathBuf,
+
+    /// Username allowed to connect over SSH, where everyone is allowed if none are given (repeatable)
+    #[cfg(feature = "ssh")]
+    #[arg(long = "ssh-user")]
+    ssh_users: Vec<String>,
 }
 
 impl InitConfig {
@@ -57,16 +417,6 @@ impl InitConfig {
         SocketAddr::new(self.addr, self.port)
     }
 
-    /// URI path
-    pub fn path(&self) -> &str {
-        &self.path
-    }
-
-    /// Path to the images.
-    pub fn gif(&self) -> Option<&Path> {
-        self.gif.as_deref()
-    }
-
     /// Frames/second, if specified.
     pub const fn fps(&self) -> Option<f32> {
         self.fps
@@ -76,11 +426,179 @@ impl InitConfig {
     pub const fn is_colored(&self) -> bool {
         self.colored || self.force_colored
     }
+
+    /// Read/write deadline of a connection that is yet to be verified.
+    pub const fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    /// Maximum number of concurrent handshakes.
+    pub const fn max_pending(&self) -> usize {
+        self.max_pending.get()
+    }
+
+    /// Maximum number of concurrent streams overall, if limited.
+    pub const fn max_streams(&self) -> Option<usize> {
+        self.max_streams
+    }
+
+    /// Maximum number of concurrent streams from a single address, if limited.
+    pub const fn max_streams_per_ip(&self) -> Option<usize> {
+        self.max_streams_per_ip
+    }
+
+    /// Average connections per second allowed from a single address, if limited.
+    pub const fn accept_rate(&self) -> Option<f64> {
+        self.accept_rate
+    }
+
+    /// Connections allowed from a single address in a burst.
+    pub const fn accept_burst(&self) -> u32 {
+        self.accept_burst.get()
+    }
+
+    /// Maximum number of headers of a request.
+    pub const fn max_headers(&self) -> usize {
+        self.max_headers.get()
+    }
+
+    /// Maximum size of the head of a request, in bytes.
+    pub const fn max_head_size(&self) -> usize {
+        self.max_head_size.get()
+    }
+
+    /// How long the streams have to end during a shutdown.
+    pub const fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    /// Message every client is left with during a shutdown, if any.
+    pub fn goodbye(&self) -> Option<&str> {
+        self.goodbye.as_deref()
+    }
+
+    /// Capacity of each client's send queue.
+    pub const fn queue_size(&self) -> usize {
+        self.queue_size.get()
+    }
+
+    /// Number of dropped frames a client is allowed, if it can be disconnected at all.
+    pub const fn max_dropped(&self) -> Option<usize> {
+        match self.lag_policy {
+            LagPolicy::Skip => None,
+            LagPolicy::Disconnect => Some(self.max_dropped),
+        }
+    }
+
+    /// Addresses allowed to connect.
+    pub fn allowed(&self) -> &[Cidr] {
+        &self.allowed
+    }
+
+    /// Addresses refused regardless of the allowed ones.
+    pub fn denied(&self) -> &[Cidr] {
+        &self.denied
+    }
+
+    /// File of access rules, if specified.
+    pub fn access_file(&self) -> Option<&Path> {
+        self.access_file.as_deref()
+    }
+
+    /// Proxies whose forwarding headers are believed.
+    pub fn trusted_proxies(&self) -> &[Cidr] {
+        &self.trusted_proxies
+    }
+
+    /// Determinant for whether connections of the listener on the port, or of the Unix domain socket for `None`,
+    /// begin with a PROXY protocol header.
+    pub fn is_proxied(&self, port: Option<u16>) -> bool {
+        self.proxied
+            .contains(&port.map_or(Proxied::Unix, Proxied::Port))
+    }
+
+    /// User to run as, if specified.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Group to run as, if specified.
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Determinant for whether the process is confined to the working directory.
+    pub const fn is_chrooted(&self) -> bool {
+        self.chroot
+    }
+
+    /// Path of the Unix domain socket, if specified.
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+
+    /// File mode of the Unix domain socket.
+    pub const fn unix_socket_mode(&self) -> u32 {
+        self.unix_socket_mode
+    }
+
+    /// Construct the [`SocketAddr`] of the raw port, if specified
+    pub fn raw_addr(&self) -> Option<SocketAddr> {
+        self.raw_port.map(|port| SocketAddr::new(self.addr, port))
+    }
+
+    /// Construct the [`SocketAddr`] of the telnet port, if specified
+    pub fn telnet_addr(&self) -> Option<SocketAddr> {
+        self.telnet_port
+            .map(|port| SocketAddr::new(self.addr, port))
+    }
+
+    /// Construct the [`SocketAddr`] of the TLS port, along with the certificate and key files, if specified
+    #[cfg(feature = "tls")]
+    pub fn tls(&self) -> Option<(SocketAddr, &Path, &Path)> {
+        Some((
+            SocketAddr::new(self.addr, self.tls_port?),
+            self.tls_cert.as_deref()?,
+            self.tls_key.as_deref()?,
+        ))
+    }
+
+    /// Construct the [`SocketAddr`] of the SSH port, along with the file of the host key, if specified
+    #[cfg(feature = "ssh")]
+    pub fn ssh(&self) -> Option<(SocketAddr, &Path)> {
+        Some((
+            SocketAddr::new(self.addr, self.ssh_port?),
+            &self.ssh_host_key,
+        ))
+    }
+
+    /// Usernames allowed to connect over SSH.
+    #[cfg(feature = "ssh")]
+    pub fn ssh_users(&self) -> &[String] {
+        &self.ssh_users
+    }
+
+    /// URI path of the animation streamed on the raw and telnet ports, if specified.
+    pub fn raw_path(&self) -> Option<&str> {
+        self.raw_path.as_deref()
+    }
+
+    /// Determinant for whether streams may be compressed.
+    pub const fn is_compressed(&self) -> bool {
+        !self.no_compression
+    }
+
+    /// Playback options of every client, before its query is applied.
+    pub fn options(&self) -> Options {
+        Options::finite(self.loops, self.duration)
+    }
 }
 
 pub struct Config {
     init: InitConfig,
-    file_name: String,
+    routes: Vec<Route>,
+    listeners: Vec<Listener>,
+    agents: AgentPolicy,
 }
 
 impl Config {
@@ -92,7 +610,7 @@ impl Config {
         let init = InitConfig::parse();
 
         // ensure intended log level
-        set_var("RUST_LOG", format!("{},artem=warn", log_level));
+        set_var("RUST_LOG", format!("{},artem=warn,russh=warn", log_level));
 
         // set color specifiers
         if init.force_colored {
@@ -100,36 +618,85 @@ impl Config {
             set_var("CLICOLOR_FORCE", "1");
         }
 
-        // the new file stem of the ascii-generated file
-        let file_stem = init
-            .gif()
-            .map(|p| {
-                p.file_stem()
-                    .map(|s| s.to_str().unwrap_or("_"))
-                    .unwrap_or("_")
+        let cfg = Self::from_init(init)?;
+
+        // init logger
+        env_logger::init();
+
+        Ok(cfg)
+    }
+
+    /// Settle the parsed program arguments into every route, listener and allowed client.
+    pub fn from_init(init: InitConfig) -> Result<Self> {
+        // every animation to be served, falling back onto `--path` and `--gif`
+        let mut routes = init.routes.clone();
+        if let Some(file) = init.routes_file.as_deref() {
+            routes.extend(read_routes(file)?)
+        }
+        if routes.is_empty() {
+            routes.push(Route {
+                path: init.path.clone(),
+                gif: init.gif.clone(),
             })
-            .unwrap_or("donuts");
+        }
 
-        // the new file name of the ascii-generated file
-        let file_name = {
-            // '.ascii' default extension
-            let mut s = format!("{}.ascii", file_stem);
+        // each path can only be served one animation
+        for (i, route) in routes.iter().enumerate() {
+            if routes[..i].iter().any(|r| r.path == route.path) {
+                return Err(Invalid::Route(format!("duplicate path {}", route.path)).into());
+            }
+        }
+
+        // every address to listen on, falling back onto `--addr` and `--port`
+        let mut listeners = init.listeners.clone();
+        if listeners.is_empty() {
+            listeners.push(Listener {
+                addr: init.addr(),
+                prefix: String::new(),
+                route: None,
+            })
+        }
 
-            // '.asciic' extension indicates colored ascii
-            if init.is_colored() {
-                s.push('c')
+        // the raw port and listeners can only stream an existing animation
+        for path in listeners
+            .iter()
+            .filter_map(Listener::route)
+            .chain(init.raw_path())
+        {
+            if !routes.iter().any(|r| r.path == path) {
+                return Err(Invalid::Route(format!("{} is not served", path)).into());
             }
-            s
-        };
+        }
 
-        // init logger
-        env_logger::init();
+        // the clients allowed to watch
+        let agents = AgentPolicy::new(
+            &init.agent_profiles,
+            &init.allowed_agents,
+            &init.denied_agents,
+            &init.accepted,
+        );
+
+        Ok(Self {
+            init,
+            routes,
+            listeners,
+            agents,
+        })
+    }
+
+    /// Every animation and the path it is served on.
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
 
-        Ok(Self { init, file_name })
+    /// Every address to listen on.
+    pub fn listeners(&self) -> &[Listener] {
+        &self.listeners
     }
 
-    pub fn file_name(&self) -> &str {
-        &self.file_name
+    /// The clients allowed to watch.
+    pub const fn agents(&self) -> &AgentPolicy {
+        &self.agents
     }
 }
 
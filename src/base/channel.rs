@@ -0,0 +1,103 @@
+use onebuck::{Bucket, ValueIndex};
+use std::sync::Arc;
+
+use super::{AsciiFrame, Client, Heartbeat, Listener, SignalLock, Timeline};
+
+/// An animation served on its own URI path, with its own clients
+pub struct Channel {
+    path: String,
+    frames: Arc<[AsciiFrame]>,
+    timeline: Arc<Timeline>,
+    streams: SignalLock<Bucket<Client>>,
+    disconnected: SignalLock<Vec<ValueIndex>>,
+    heartbeat: Heartbeat,
+}
+
+impl Channel {
+    pub fn new(path: &str, frames: Vec<AsciiFrame>) -> Self {
+        Self {
+            path: path.to_owned(),
+            timeline: Arc::new(Timeline::new(&frames)),
+            frames: frames.into(),
+            streams: SignalLock::default(),
+            disconnected: SignalLock::default(),
+            heartbeat: Heartbeat::default(),
+        }
+    }
+
+    /// URI path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The frames of the animation.
+    pub fn frames(&self) -> &Arc<[AsciiFrame]> {
+        &self.frames
+    }
+
+    /// The offset of every frame.
+    pub fn timeline(&self) -> &Arc<Timeline> {
+        &self.timeline
+    }
+
+    /// Connected clients.
+    pub const fn streams(&self) -> &SignalLock<Bucket<Client>> {
+        &self.streams
+    }
+
+    /// Disconnected clients.
+    pub const fn disconnected(&self) -> &SignalLock<Vec<ValueIndex>> {
+        &self.disconnected
+    }
+
+    /// Progress of the distributor.
+    pub const fn heartbeat(&self) -> &Heartbeat {
+        &self.heartbeat
+    }
+}
+
+/// A channel along with the URI path a listener serves it on
+pub struct Mount {
+    path: String,
+    channel: Arc<Channel>,
+}
+
+impl Mount {
+    /// Every channel on its own path.
+    pub fn all(channels: &[Arc<Channel>]) -> Arc<[Self]> {
+        channels
+            .iter()
+            .map(|c| Self {
+                path: c.path().to_owned(),
+                channel: c.clone(),
+            })
+            .collect()
+    }
+
+    /// The channels of a listener, either every one under its prefix or only that of its route at the prefix itself.
+    pub fn listener(listener: &Listener, channels: &[Arc<Channel>]) -> Arc<[Self]> {
+        let prefix = listener.prefix();
+        channels
+            .iter()
+            .filter(|c| listener.route().is_none_or(|route| c.path() == route))
+            .map(|c| Self {
+                path: match (listener.route(), c.path()) {
+                    (Some(_), _) | (None, "/") if !prefix.is_empty() => prefix.to_owned(),
+                    (Some(_), _) => "/".to_owned(),
+                    (None, path) => format!("{}{}", prefix, path),
+                },
+                channel: c.clone(),
+            })
+            .collect()
+    }
+
+    /// URI path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The channel served on the path.
+    pub const fn channel(&self) -> &Arc<Channel> {
+        &self.channel
+    }
+}
@@ -0,0 +1,162 @@
+use std::{net::IpAddr, str::FromStr};
+
+/// A block of IP addresses, such as `10.0.0.0/8` or `::1/128`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Number of bits of an address of the same family.
+    const fn bits(addr: IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Determinant for whether the address lies within the block, where IPv4-mapped IPv6 addresses count as IPv4.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // the bits in which the addresses differ
+        let diff = match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => u128::from(u32::from(net) ^ u32::from(addr)),
+            (IpAddr::V6(net), IpAddr::V6(addr)) => u128::from(net) ^ u128::from(addr),
+            _ => return false,
+        };
+        self.prefix == 0 || diff >> (Self::bits(self.addr) - self.prefix) == 0
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse `ADDR[/PREFIX]`, where a bare address is a block of its own.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').map_or((s, None), |(a, p)| (a, Some(p)));
+        let addr = IpAddr::from_str(addr).map_err(|e| e.to_string())?;
+        let prefix = match prefix {
+            Some(p) if p.bytes().all(|b| b.is_ascii_digit()) => {
+                p.parse::<u8>().map_err(|e| e.to_string())?
+            }
+            Some(p) => return Err(format!("prefix /{} is not a number", p)),
+            None => Self::bits(addr),
+        };
+        if prefix > Self::bits(addr) {
+            return Err(format!("prefix /{} is too long", prefix));
+        }
+
+        // blocks of IPv4-mapped IPv6 addresses are blocks of IPv4 addresses, just like the addresses themselves
+        match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() && prefix >= 96 => Ok(Self {
+                addr: v4.into(),
+                prefix: prefix - 96,
+            }),
+            _ => Ok(Self { addr, prefix }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn contains(cidr: &str, addr: &str) -> bool {
+        self::cidr(cidr).contains(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn whole_families() {
+        assert!(contains("0.0.0.0/0", "0.0.0.0"));
+        assert!(contains("0.0.0.0/0", "255.255.255.255"));
+        assert!(contains("0.0.0.0/0", "::ffff:192.0.2.1"));
+        assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+
+        assert!(contains("::/0", "::"));
+        assert!(contains("::/0", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+        assert!(!contains("::/0", "192.0.2.1"));
+    }
+
+    #[test]
+    fn single_addresses() {
+        assert!(contains("192.0.2.1/32", "192.0.2.1"));
+        assert!(!contains("192.0.2.1/32", "192.0.2.0"));
+        assert!(!contains("192.0.2.1/32", "192.0.2.2"));
+        assert_eq!(cidr("192.0.2.1"), cidr("192.0.2.1/32"));
+
+        assert!(contains("2001:db8::1/128", "2001:db8::1"));
+        assert!(!contains("2001:db8::1/128", "2001:db8::"));
+        assert!(!contains("2001:db8::1/128", "2001:db8::2"));
+        assert_eq!(cidr("2001:db8::1"), cidr("2001:db8::1/128"));
+    }
+
+    #[test]
+    fn prefixes() {
+        assert!(contains("10.0.0.0/8", "10.255.255.255"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.0"));
+        assert!(!contains("10.0.0.0/8", "9.255.255.255"));
+        assert!(contains("192.0.2.128/25", "192.0.2.200"));
+        assert!(!contains("192.0.2.128/25", "192.0.2.127"));
+
+        assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+        assert!(contains("fe80::/10", "febf::1"));
+        assert!(!contains("fe80::/10", "fec0::1"));
+    }
+
+    #[test]
+    fn host_bits_set() {
+        // only the bits of the prefix count
+        assert!(contains("10.1.2.3/8", "10.200.0.1"));
+        assert!(!contains("10.1.2.3/8", "11.1.2.3"));
+        assert!(contains("2001:db8::1/64", "2001:db8::ffff"));
+        assert!(!contains("2001:db8::1/64", "2001:db8:0:1::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        // mapped addresses lie within IPv4 blocks, but not within IPv6 ones
+        assert!(contains("192.0.2.0/24", "::ffff:192.0.2.1"));
+        assert!(!contains("192.0.2.0/24", "::ffff:198.51.100.1"));
+        assert!(!contains("::/1", "::ffff:192.0.2.1"));
+
+        // mapped blocks are IPv4 blocks
+        assert_eq!(cidr("::ffff:192.0.2.1"), cidr("192.0.2.1"));
+        assert_eq!(cidr("::ffff:192.0.2.0/120"), cidr("192.0.2.0/24"));
+        assert_eq!(cidr("::ffff:0.0.0.0/96"), cidr("0.0.0.0/0"));
+        assert!(contains("::ffff:192.0.2.0/120", "192.0.2.1"));
+        assert!(contains("::ffff:192.0.2.0/120", "::ffff:192.0.2.1"));
+
+        // prefixes that reach into the mapping itself stay IPv6 blocks, which never contain IPv4 addresses
+        assert!(contains("::ffff:0.0.0.0/95", "::fffe:0:1"));
+        assert!(!contains("::ffff:0.0.0.0/95", "::ffff:192.0.2.1"));
+        assert!(!contains("::ffff:0.0.0.0/95", "192.0.2.1"));
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "",
+            "/8",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "::/129",
+            "::ffff:10.0.0.0/129",
+            "10.0.0.0/-1",
+            "10.0.0.0/+8",
+            "10.0.0.0/8/8",
+            "10.0.0.0/ 8",
+            "10.0.0.0/256",
+            "10.0.0/8",
+            "10.0.0.256",
+            "[::1]/128",
+            "example.com/8",
+        ] {
+            assert!(s.parse::<Cidr>().is_err(), "{:?}", s)
+        }
+    }
+}
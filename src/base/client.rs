@@ -1,37 +1,369 @@
-use std::net::{SocketAddr, TcpStream};
+use log::debug;
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::{Shutdown, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{spawn, JoinHandle},
+};
+
+use super::{
+    restore_terminal, write_events, write_messages, AsciiFrame, Body, Encoder, Encoding, Farewell,
+    Framing, Permit, Playback, Push, Queue, Response, Result, Step, Stream, Viewer, WebSocket,
+    Window,
+};
 
-#[derive(Debug)]
 pub struct Client {
-    inner: TcpStream,
+    inner: Stream,
     addr: SocketAddr,
+    queue: Queue<usize>,
+    max_dropped: Option<usize>,
+    disconnected: AtomicBool,
+    farewell: Farewell,
+    writer: JoinHandle<()>,
+    _permit: Permit,
+}
+
+/// Clears the screen before the first frame
+const CLEAR: &[u8] = b"\x1b[2J";
+
+/// Determinant for whether a client has fallen too far behind to be kept, given the frames it has missed in a row.
+fn is_lagging(dropped: usize, max_dropped: Option<usize>) -> bool {
+    max_dropped.is_some_and(|max| dropped > max)
+}
+
+/// Send every queued frame according to the playback, until either the stream or the queue closes.
+fn write_frames(
+    stream: Stream,
+    frames: &[AsciiFrame],
+    mut playback: Playback,
+    framing: Framing,
+    encoding: Encoding,
+    queue: &Queue<usize>,
+    window: Option<&Window>,
+) -> std::io::Result<()> {
+    // each flush sends the compressed frame as a chunk of its own
+    let mut body = Encoder::new(Body::new(stream, framing), encoding)?;
+
+    // setup the client's terminal
+    body.write_all(CLEAR)?;
+    body.flush()?;
+
+    // the frame currently on screen
+    let mut last = None;
+
+    // the size of the terminal every frame is fitted to, if known, along with every frame fitted so far
+    let mut size = window.map(Window::size);
+    let mut fitted = HashMap::new();
+
+    while let Some(i) = queue.pop() {
+        // clients of a terminal can end the stream themselves
+        let step = match window {
+            Some(window) if window.is_hung_up() => Step::End,
+            _ => playback.advance(i),
+        };
+        let (j, frame) = match step {
+            Step::Frame(j) => (j, &frames[j]),
+            Step::Skip => continue,
+            Step::End => {
+                // restore the terminal then end the stream
+                let trailer = match size {
+                    Some((_, rows)) if last.is_some() => restore_terminal(rows),
+                    _ => last.map(AsciiFrame::trailer).unwrap_or_default(),
+                };
+                body.write_all(&trailer)?;
+                // leave the goodbye message of a shutdown below the frame
+                if let Some(goodbye) = playback.farewell().filter(|m| !m.is_empty()) {
+                    body.write_fmt(format_args!("{}\r\n", goodbye))?
+                }
+                body.finish()?.finish()?.shutdown(Shutdown::Write)?;
+                break;
+            }
+        };
+        if let Some(window) = window {
+            // redraw from scratch whenever the terminal is resized
+            let (cols, rows) = window.size();
+            if size != Some((cols, rows)) {
+                size = Some((cols, rows));
+                fitted.clear();
+                body.write_all(CLEAR)?;
+            }
+            let fitted = fitted
+                .entry(j)
+                .or_insert_with(|| frame.fit(cols, rows, playback.is_colored()));
+            body.write_all(fitted)?
+        } else if playback.is_colored() {
+            body.write_all(frame.as_ref())?
+        } else {
+            body.write_all(&frame.uncolored())?
+        }
+        body.flush()?;
+        last = Some(frame);
+    }
+    Ok(())
 }
 
 impl Client {
-    pub const fn new(inner: TcpStream, addr: SocketAddr) -> Self {
-        Self { inner, addr }
+    /// Spawn the writer of the stream, which sends every queued frame according to its playback.
+    pub fn new(
+        inner: Stream,
+        viewer: Viewer,
+        permit: Permit,
+        queue_size: usize,
+        max_dropped: Option<usize>,
+    ) -> Result<Self> {
+        let Viewer {
+            addr,
+            channel,
+            options,
+            framing,
+            encoding,
+            response,
+            window,
+        } = viewer;
+
+        let queue = Queue::new(queue_size);
+        let farewell = Farewell::default();
+        let playback = Playback::new(options, channel.timeline().clone(), farewell.clone());
+
+        // only the writer ever blocks on a slow connection
+        let stream = inner.try_clone()?;
+        let writer_queue = queue.clone();
+
+        // telnet clients keep reporting the size of their terminal among what they type, whereas the SSH session
+        // handles the requests and keys of its client itself
+        match (&window, &inner) {
+            #[cfg(feature = "ssh")]
+            (Some(_), Stream::Ssh(_)) => {}
+            (Some(window), _) => window.clone().listen(inner.try_clone()?),
+            (None, _) => {}
+        }
+
+        // WebSocket clients are pinged by a reader of their own, which also answers their pings
+        let websocket = match response {
+            Response::WebSocket(upgrade) => {
+                let websocket = Arc::new(WebSocket::new(inner.try_clone()?, upgrade));
+                websocket.clone().keep_alive(inner.try_clone()?, addr);
+                Some(websocket)
+            }
+            _ => None,
+        };
+
+        let writer = spawn(move || {
+            let frames = channel.frames();
+            let window = window.as_deref();
+            let res = match &websocket {
+                Some(websocket) => write_messages(websocket, frames, playback, &writer_queue),
+                None if response == Response::Events => {
+                    write_events(stream, frames, playback, framing, encoding, &writer_queue)
+                }
+                None => write_frames(
+                    stream,
+                    frames,
+                    playback,
+                    framing,
+                    encoding,
+                    &writer_queue,
+                    window,
+                ),
+            };
+            match res {
+                Ok(()) => debug!("Closed {}", addr),
+                Err(e) => debug!("Lost {}: {}", addr, e),
+            }
+            // let the distributor know the stream is gone
+            writer_queue.close()
+        });
+
+        Ok(Self {
+            inner,
+            addr,
+            queue,
+            max_dropped,
+            disconnected: AtomicBool::new(false),
+            farewell,
+            writer,
+            _permit: permit,
+        })
     }
 
     pub const fn addr(&self) -> SocketAddr {
         self.addr
     }
-}
 
-impl std::io::Write for Client {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.inner.write(buf)
+    /// Queue the frame at the given index, returning `false` if the client should be disconnected.
+    pub fn send(&self, frame_index: usize) -> bool {
+        match self.queue.push(frame_index) {
+            Push::Dropped(n) if is_lagging(n, self.max_dropped) => {
+                debug!("Lagging {}: dropped {} frames", self.addr(), n);
+                false
+            }
+            Push::Queued | Push::Dropped(_) => true,
+            Push::Closed => false,
+        }
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.inner.flush()
+    /// Stop the writer and close the connection, returning `true` only on the first call.
+    pub fn disconnect(&self) -> bool {
+        if self.disconnected.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        self.close();
+        true
+    }
+
+    /// End the stream as the server shuts down, restoring the terminal and leaving the goodbye message, if any.
+    pub fn farewell(&self, goodbye: Option<&str>) {
+        // the writer ends the stream itself, so the connection stays open until it's done
+        if self.disconnected.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.farewell.say(goodbye.unwrap_or_default());
+
+        // wake the writer, which says goodbye instead of sending the frame
+        self.queue.push(0);
+    }
+
+    /// Stop the writer and close the connection, whether or not the writer is done with it.
+    pub fn close(&self) {
+        self.queue.close();
+        _ = self.inner.shutdown(Shutdown::Both);
+    }
+
+    /// Determinant for whether the writer has ended, one way or another.
+    pub fn is_finished(&self) -> bool {
+        self.writer.is_finished()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.disconnect();
     }
 }
 
-impl std::io::Write for &Client {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        (&self.inner).write(buf)
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::base::{Options, Timeline};
+
+    /// Frames two lines high, so the trailer moves the cursor below the second one.
+    fn frames() -> Vec<AsciiFrame> {
+        [&b"a\nb"[..], b"c\nd", b"e\nf"]
+            .map(|f| AsciiFrame::new(f.to_vec(), Duration::from_millis(10)))
+            .to_vec()
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        (&self.inner).flush()
+    /// Run the writer of a plain stream on a thread of its own, handing back the queue it follows
+    /// along with the other end of the connection.
+    fn writer(options: Options, farewell: &Farewell) -> (Queue<usize>, TcpStream, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = Stream::Tcp(listener.accept().unwrap().0);
+
+        let frames = frames();
+        let playback = Playback::new(options, Arc::new(Timeline::new(&frames)), farewell.clone());
+        let queue = Queue::new(16);
+        let writer_queue = queue.clone();
+        let writer = spawn(move || {
+            let res = write_frames(
+                stream,
+                &frames,
+                playback,
+                Framing::Close,
+                Encoding::Identity,
+                &writer_queue,
+                None,
+            );
+            assert!(res.is_ok());
+        });
+        (queue, peer, writer)
+    }
+
+    #[test]
+    fn lagging() {
+        let queue = Queue::new(2);
+        let lags = |max_dropped| match queue.push(0) {
+            Push::Dropped(n) => is_lagging(n, max_dropped),
+            _ => false,
+        };
+
+        // a stalled client misses the whole backlog every other frame
+        assert!(!lags(Some(3)));
+        assert!(!lags(Some(3)));
+        assert!(!lags(Some(3)));
+        assert!(!lags(Some(3)));
+        assert!(lags(Some(3)));
+
+        // until it makes progress, or forever if it can't be disconnected, having missed 100 frames by the next one
+        queue.pop();
+        assert!((0..100).all(|_| !lags(None)));
+        assert!(lags(Some(99)));
+    }
+
+    #[test]
+    fn finite_loops() {
+        let farewell = Farewell::default();
+        let (queue, mut peer, writer) = writer(Options::finite(Some(2), None), &farewell);
+
+        // the frame after the second loop ends the stream, while those queued after it are never sent
+        for i in [1, 2, 0, 1, 2, 0, 1, 2, 0] {
+            queue.push(i);
+        }
+        let mut buf = Vec::new();
+        peer.read_to_end(&mut buf).unwrap();
+        writer.join().unwrap();
+
+        let frames = frames();
+        let mut expected = CLEAR.to_vec();
+        for i in [1, 2, 0, 1, 2, 0] {
+            expected.extend_from_slice(frames[i].as_ref());
+        }
+        expected.extend(frames[0].trailer());
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn trailer_before_goodbye() {
+        let farewell = Farewell::default();
+        let (queue, mut peer, writer) = writer(Options::default(), &farewell);
+        let frames = frames();
+
+        // wait for the first frame to arrive before the server shuts down
+        queue.push(1);
+        let mut buf = vec![0; CLEAR.len() + frames[1].as_ref().len()];
+        peer.read_exact(&mut buf).unwrap();
+
+        farewell.say("bye");
+        queue.push(2);
+        let mut buf = Vec::new();
+        peer.read_to_end(&mut buf).unwrap();
+        writer.join().unwrap();
+
+        // the terminal is restored below the frame on screen, rather than the one that was due
+        assert_eq!(buf, [&frames[1].trailer()[..], b"bye\r\n"].concat());
+    }
+
+    #[test]
+    fn goodbye_without_frames() {
+        let farewell = Farewell::default();
+        farewell.say("bye");
+        let (queue, mut peer, writer) = writer(Options::default(), &farewell);
+        queue.push(0);
+        let mut buf = Vec::new();
+        peer.read_to_end(&mut buf).unwrap();
+        writer.join().unwrap();
+
+        // there is no frame to restore the terminal after
+        assert_eq!(buf, [CLEAR, b"bye\r\n"].concat());
     }
 }
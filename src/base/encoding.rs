@@ -0,0 +1,167 @@
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::io::{Result, Write};
+
+/// Content coding of a streaming response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Every supported compression, in order of preference.
+    const PREFERRED: [Self; 3] = [Self::Zstd, Self::Gzip, Self::Deflate];
+
+    /// Name of the coding as used by `Accept-Encoding` and `Content-Encoding`.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Choose the compression most preferred by the client, then the server, from an `Accept-Encoding` header.
+    pub fn negotiate(accept_encoding: &str) -> Self {
+        // each accepted coding along with its quality value
+        let accepted = accept_encoding
+            .split(',')
+            .filter_map(|item| {
+                let mut params = item.split(';').map(str::trim);
+                let name = params.next().filter(|n| !n.is_empty())?;
+                let quality = params
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                Some((name, quality))
+            })
+            .collect::<Vec<_>>();
+
+        // `*` stands for every coding that isn't listed on its own
+        let quality = |encoding: &Self| {
+            let find = |coding: &str| {
+                accepted
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(coding))
+            };
+            find(encoding.name())
+                .or_else(|| find("*"))
+                .map_or(0.0, |(_, q)| *q)
+        };
+
+        // a client may prefer to go without compression, though only if it says so
+        let identity = accepted
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(Self::Identity.name()))
+            .map_or(0.0, |(_, q)| *q);
+
+        Self::PREFERRED
+            .into_iter()
+            .filter(|e| quality(e) > 0.0 && quality(e) >= identity)
+            .fold(None, |best: Option<Self>, e| match best {
+                Some(b) if quality(&b) >= quality(&e) => Some(b),
+                _ => Some(e),
+            })
+            .unwrap_or(Self::Identity)
+    }
+}
+
+/// A streaming compressor, where every flush makes everything written so far decodable by the client
+pub enum Encoder<W: Write> {
+    Identity(W),
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, encoding: Encoding) -> Result<Self> {
+        Ok(match encoding {
+            Encoding::Identity => Self::Identity(inner),
+            Encoding::Zstd => Self::Zstd(zstd::Encoder::new(inner, 0)?),
+            Encoding::Gzip => Self::Gzip(GzEncoder::new(inner, Compression::default())),
+            Encoding::Deflate => Self::Deflate(ZlibEncoder::new(inner, Compression::default())),
+        })
+    }
+
+    /// Complete the compressed stream, returning the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        match self {
+            Self::Identity(w) => Ok(w),
+            Self::Zstd(e) => e.finish(),
+            Self::Gzip(e) => e.finish(),
+            Self::Deflate(e) => e.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::Identity(w) => w.write(buf),
+            Self::Zstd(e) => e.write(buf),
+            Self::Gzip(e) => e.write(buf),
+            Self::Deflate(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Identity(w) => w.flush(),
+            Self::Zstd(e) => e.flush(),
+            Self::Gzip(e) => e.flush(),
+            Self::Deflate(e) => e.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        for (accept_encoding, encoding) in [
+            ("", Encoding::Identity),
+            ("gzip", Encoding::Gzip),
+            ("GZIP ; q=0.5", Encoding::Gzip),
+            // the client's preference comes first
+            ("gzip;q=0.5, deflate", Encoding::Deflate),
+            ("zstd;q=0.1, gzip;q=0.9, deflate;q=0.5", Encoding::Gzip),
+            // ties go to the server's preference
+            ("deflate, gzip", Encoding::Gzip),
+            ("deflate;q=0.8, gzip;q=0.8, zstd;q=0.8", Encoding::Zstd),
+            // a quality of zero refuses the coding, as does a quality that isn't a number
+            ("gzip;q=0", Encoding::Identity),
+            ("gzip;q=0.000, deflate", Encoding::Deflate),
+            ("zstd;q=high, gzip;q=0.1", Encoding::Gzip),
+            // identity is what's left, whether refused or not, unless the client prefers it
+            ("identity;q=0", Encoding::Identity),
+            ("identity;q=0, deflate", Encoding::Deflate),
+            ("identity, deflate;q=0.1", Encoding::Identity),
+            ("identity;q=0.5, gzip;q=0.5", Encoding::Gzip),
+            ("*, identity", Encoding::Zstd),
+            // unknown codings are skipped
+            ("br", Encoding::Identity),
+            ("br, compress;q=1, deflate;q=0.2", Encoding::Deflate),
+            // `*` covers whatever isn't listed
+            ("*", Encoding::Zstd),
+            ("zstd;q=0, *", Encoding::Gzip),
+            ("*;q=0.5, deflate", Encoding::Deflate),
+            ("*;q=0", Encoding::Identity),
+            ("*;q=0, gzip", Encoding::Gzip),
+        ] {
+            assert_eq!(
+                Encoding::negotiate(accept_encoding),
+                encoding,
+                "{}",
+                accept_encoding
+            );
+        }
+    }
+}
@@ -30,9 +30,61 @@ pub enum UriError {
     HttpParse(httparse::Error),
     Method(String),
     Path(String),
-    Version(u8),
     Header(String),
+    Query(String),
     Addr(AddrError),
+    TooLarge(usize),
+    Upgrade(String),
+}
+
+impl UriError {
+    /// Status line of the response rejecting the request.
+    pub const fn status(&self) -> &'static str {
+        match self {
+            Self::Method(_) => "405 Method Not Allowed",
+            Self::Path(_) => "404 Not Found",
+            Self::HttpParse(httparse::Error::Version) => "505 HTTP Version Not Supported",
+            Self::Header(_) => "406 Not Acceptable",
+            Self::TooLarge(_) | Self::HttpParse(httparse::Error::TooManyHeaders) => {
+                "431 Request Header Fields Too Large"
+            }
+            Self::HttpParse(_) | Self::Query(_) | Self::Addr(_) | Self::Upgrade(_) => {
+                "400 Bad Request"
+            }
+        }
+    }
+
+    /// Additional headers of the response rejecting the request.
+    pub const fn headers(&self) -> &'static str {
+        match self {
+            Self::Method(_) => "Allow: GET\r\n",
+            Self::Upgrade(_) => "Sec-WebSocket-Version: 13\r\n",
+            _ => "",
+        }
+    }
+
+    /// Human-readable explanation of why the request was rejected.
+    pub fn explain(&self) -> String {
+        match self {
+            Self::HttpParse(httparse::Error::Version) => {
+                "Only HTTP/1.0 and HTTP/1.1 are supported.".to_owned()
+            }
+            Self::HttpParse(httparse::Error::TooManyHeaders) => {
+                "The request has too many headers.".to_owned()
+            }
+            Self::HttpParse(e) => format!("The request could not be parsed ({}).", e),
+            Self::Method(s) => format!("{} is not supported, only GET is.", s),
+            Self::Path(s) => format!("Nothing is being served on {}.", s),
+            Self::Header(s) => format!(
+                "This stream is meant for terminals, but {} is not supported.",
+                s
+            ),
+            Self::Query(s) => format!("The query is invalid: {}.", s),
+            Self::Addr(e) => format!("The forwarded address is invalid ({}).", e),
+            Self::TooLarge(n) => format!("The head of the request exceeds {} bytes.", n),
+            Self::Upgrade(s) => format!("The WebSocket upgrade is invalid: {}.", s),
+        }
+    }
 }
 
 impl<'a> From<&httparse::Header<'a>> for UriError {
@@ -63,9 +115,11 @@ impl std::fmt::Display for UriError {
             Self::HttpParse(e) => e.to_string(),
             Self::Method(s) => format!("method {}", s),
             Self::Path(s) => format!("path {}", s),
-            Self::Version(v) => format!("version {}", v),
             Self::Header(s) => format!("header {}", s),
+            Self::Query(s) => format!("query {}", s),
             Self::Addr(e) => format!("address => {}", e),
+            Self::TooLarge(n) => format!("head exceeds {} bytes", n),
+            Self::Upgrade(s) => format!("upgrade {}", s),
         })
     }
 }
@@ -74,6 +128,9 @@ pub enum Invalid {
     Uri(UriError),
     Format,
     Level,
+    Route(String),
+    Proxy,
+    Access(String),
 }
 
 impl<T: Into<UriError>> From<T> for Invalid {
@@ -96,6 +153,9 @@ impl std::fmt::Display for Invalid {
                 Self::Uri(e) => e.to_string(),
                 Self::Format => "http format".to_string(),
                 Self::Level => "log level".to_string(),
+                Self::Route(s) => format!("route {}", s),
+                Self::Proxy => "PROXY protocol header".to_string(),
+                Self::Access(s) => format!("access list {}", s),
             }
         ))
     }
@@ -125,6 +185,15 @@ pub enum Error {
     Gif(GifError),
     Json(bincode::Error),
     Cli(indicatif::style::TemplateError),
+    #[cfg(feature = "tls")]
+    Tls(rustls::Error),
+    #[cfg(feature = "tls")]
+    Pem(rustls::pki_types::pem::Error),
+    #[cfg(feature = "ssh")]
+    Ssh(russh::Error),
+    #[cfg(feature = "ssh")]
+    HostKey(russh::keys::Error),
+    Limit(super::Limit),
     Empty,
     Sync,
 }
@@ -141,6 +210,40 @@ impl<T: Into<Invalid>> From<T> for Error {
     }
 }
 
+#[cfg(feature = "tls")]
+impl From<rustls::Error> for Error {
+    fn from(value: rustls::Error) -> Self {
+        Self::Tls(value)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<rustls::pki_types::pem::Error> for Error {
+    fn from(value: rustls::pki_types::pem::Error) -> Self {
+        Self::Pem(value)
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl From<russh::Error> for Error {
+    fn from(value: russh::Error) -> Self {
+        Self::Ssh(value)
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl From<russh::keys::Error> for Error {
+    fn from(value: russh::keys::Error) -> Self {
+        Self::HostKey(value)
+    }
+}
+
+impl From<super::Limit> for Error {
+    fn from(value: super::Limit) -> Self {
+        Self::Limit(value)
+    }
+}
+
 impl From<gif::DecodingError> for Error {
     fn from(value: gif::DecodingError) -> Self {
         Self::Gif(GifError::Gif(value))
@@ -203,6 +306,15 @@ impl std::fmt::Display for Error {
             Self::Gif(e) => e.to_string(),
             Self::Json(e) => e.to_string(),
             Self::Cli(e) => e.to_string(),
+            #[cfg(feature = "tls")]
+            Self::Tls(e) => e.to_string(),
+            #[cfg(feature = "tls")]
+            Self::Pem(e) => format!("PEM file => {}", e),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(e) => format!("SSH => {}", e),
+            #[cfg(feature = "ssh")]
+            Self::HostKey(e) => format!("SSH host key => {}", e),
+            Self::Limit(e) => format!("limit of {}", e),
             Self::Empty => "The server is empty. Entering idle mode.".to_string(),
             Self::Sync => "An unexpected (poison or thread) error has occurred".to_string(),
         })
@@ -0,0 +1,48 @@
+use std::{io::Write, net::Shutdown};
+
+use super::{AsciiFrame, Body, Encoder, Encoding, Framing, Playback, Queue, Step, Stream};
+
+/// How long a disconnected `EventSource` waits before it reconnects, in milliseconds
+const RETRY: u32 = 1000;
+
+/// Send every queued frame as a Server-Sent Event according to the playback, until either the stream or the queue closes.
+/// Every event is identified by the index of its frame, which a reconnecting client sends back through `Last-Event-ID`.
+pub fn write_events(
+    stream: Stream,
+    frames: &[AsciiFrame],
+    mut playback: Playback,
+    framing: Framing,
+    encoding: Encoding,
+    queue: &Queue<usize>,
+) -> std::io::Result<()> {
+    // each flush sends the compressed event as a chunk of its own
+    let mut body = Encoder::new(Body::new(stream, framing), encoding)?;
+
+    body.write_fmt(format_args!("retry: {}\n\n", RETRY))?;
+    body.flush()?;
+
+    while let Some(i) = queue.pop() {
+        let (j, frame) = match playback.advance(i) {
+            Step::Frame(j) => (j, &frames[j]),
+            Step::Skip => continue,
+            Step::End => {
+                // let the client know not to reconnect, along with the goodbye message of a shutdown, then end the stream
+                body.write_all(b"event: end\n")?;
+                for line in playback.farewell().unwrap_or_default().split('\n') {
+                    body.write_fmt(format_args!("data: {}\n", line))?
+                }
+                body.write_all(b"\n")?;
+                body.finish()?.finish()?.shutdown(Shutdown::Write)?;
+                break;
+            }
+        };
+        // the JSON of a frame never spans more than a single line
+        body.write_fmt(format_args!(
+            "id: {}\ndata: {}\n\n",
+            j,
+            frame.to_json(j, playback.is_colored())
+        ))?;
+        body.flush()?;
+    }
+    Ok(())
+}
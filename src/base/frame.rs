@@ -50,9 +50,162 @@ impl AsciiFrame {
         self.delay
     }
 
+    /// Delay in whole milliseconds, for clients that pace the frames themselves.
+    pub fn delay_millis(&self) -> u32 {
+        u32::try_from(self.delay.as_millis()).unwrap_or(u32::MAX)
+    }
+
     pub fn prepend_home_esc(&mut self) {
         self.buffer.splice(0..0, "\x1b[H".bytes());
     }
+
+    /// Number of lines the frame takes up.
+    pub fn height(&self) -> usize {
+        self.buffer.iter().filter(|b| **b == b'\n').count() + 1
+    }
+
+    /// Restores the terminal after this frame, by resetting every attribute,
+    /// showing the cursor and moving it onto a new line below the frame.
+    pub fn trailer(&self) -> Vec<u8> {
+        restore_terminal(self.height())
+    }
+
+    /// The frame scaled down to fit a terminal of the given size, if needed, then centered within it.
+    /// Each line is positioned on its own, and the frame is never scaled up.
+    pub fn fit(&self, cols: usize, rows: usize, colored: bool) -> Vec<u8> {
+        // every rendition in effect since the last reset, where each character refers to the one it is drawn with
+        let mut styles = vec![Vec::new()];
+        // the lines of characters, as their style along with their range within the buffer
+        let mut lines: Vec<Vec<(usize, usize, usize)>> = vec![Vec::new()];
+
+        let mut i = 0;
+        while i < self.buffer.len() {
+            let b = self.buffer[i];
+            if b == 0x1b && self.buffer.get(i + 1) == Some(&b'[') {
+                // the sequence ends with its final byte
+                let end = self.buffer[i + 2..]
+                    .iter()
+                    .position(|c| (0x40..=0x7e).contains(c))
+                    .map_or(self.buffer.len(), |p| i + 3 + p);
+                let seq = &self.buffer[i..end];
+
+                // keep graphic renditions, drop cursor movement
+                if colored && seq.ends_with(b"m") {
+                    styles.push(match seq {
+                        b"\x1b[m" | b"\x1b[0m" => Vec::new(),
+                        _ => [styles[styles.len() - 1].as_slice(), seq].concat(),
+                    });
+                }
+                i = end;
+                continue;
+            }
+
+            let line = lines.last_mut().expect("there is always a line");
+            match b {
+                b'\n' => lines.push(Vec::new()),
+                b'\r' => {}
+                // continuation bytes belong to the character before them
+                0x80..=0xbf => {
+                    if let Some(c) = line.last_mut() {
+                        c.2 = i + 1
+                    }
+                }
+                _ => line.push((styles.len() - 1, i, i + 1)),
+            }
+            i += 1;
+        }
+
+        // keep the aspect ratio of the frame
+        let width = lines.iter().map(Vec::len).max().unwrap_or_default();
+        let height = lines.len();
+        let scale = (width as f64 / cols.max(1) as f64)
+            .max(height as f64 / rows.max(1) as f64)
+            .max(1.0);
+        let (w, h) = (
+            (width as f64 / scale) as usize,
+            (height as f64 / scale) as usize,
+        );
+        let (left, top) = (cols.saturating_sub(w) / 2, rows.saturating_sub(h) / 2);
+
+        // sample the nearest character of each cell
+        let mut out = Vec::new();
+        for y in 0..h {
+            let line = &lines[(y as f64 * scale) as usize];
+            out.extend(format!("\x1b[{};{}H", top + y + 1, left + 1).bytes());
+
+            let mut current: &[u8] = &[];
+            for x in 0..w {
+                let Some(&(style, start, end)) = line.get((x as f64 * scale) as usize) else {
+                    break;
+                };
+                if styles[style] != current {
+                    current = &styles[style];
+                    out.extend(b"\x1b[0m");
+                    out.extend(current);
+                }
+                out.extend(&self.buffer[start..end]);
+            }
+            if !current.is_empty() {
+                out.extend(b"\x1b[0m");
+            }
+        }
+        out
+    }
+
+    /// The frame buffer without any `ESC[...m` color and attribute sequences.
+    pub fn uncolored(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.buffer.len());
+        let mut bytes = self.buffer.iter().copied().peekable();
+
+        while let Some(b) = bytes.next() {
+            if b == 0x1b && bytes.peek() == Some(&b'[') {
+                // collect the sequence up until its final byte
+                let mut seq = vec![b, b'['];
+                bytes.next();
+                for c in bytes.by_ref() {
+                    seq.push(c);
+                    if (0x40..=0x7e).contains(&c) {
+                        break;
+                    }
+                }
+                // keep cursor movement, drop graphic renditions
+                if seq.last() != Some(&b'm') {
+                    out.extend(seq)
+                }
+            } else {
+                out.push(b)
+            }
+        }
+        out
+    }
+
+    /// The frame on its own, without sending the cursor home, for clients that receive every frame as a message.
+    pub fn standalone(&self, colored: bool) -> Vec<u8> {
+        let buffer = match colored {
+            true => self.buffer.clone(),
+            false => self.uncolored(),
+        };
+        match buffer.strip_prefix(b"\x1b[H") {
+            Some(rest) => rest.to_vec(),
+            None => buffer,
+        }
+    }
+
+    /// The frame as a JSON object of its `index` within the animation, its `delay` in milliseconds and the `frame` itself.
+    pub fn to_json(&self, index: usize, colored: bool) -> String {
+        serde_json::json!({
+            "index": index,
+            "delay": self.delay_millis(),
+            "frame": String::from_utf8_lossy(&self.standalone(colored)),
+        })
+        .to_string()
+    }
+}
+
+/// Restores the terminal, by resetting every attribute,
+/// showing the cursor and moving it onto a new line below the given row.
+pub fn restore_terminal(row: usize) -> Vec<u8> {
+    format!("\x1b[0m\x1b[?25h\x1b[{};1H\r\n", row).into_bytes()
 }
 
 impl AsRef<[u8]> for AsciiFrame {
@@ -0,0 +1,168 @@
+use std::{
+    io::{Result, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use super::{Channel, Encoding, Options, Upgrade, Window};
+
+/// How the end of a streaming response body is indicated
+#[derive(Clone, Copy, Debug)]
+pub enum Framing {
+    /// `Transfer-Encoding: chunked`, for HTTP/1.1
+    Chunked,
+    /// The body ends when the connection closes, for HTTP/1.0
+    Close,
+}
+
+impl Framing {
+    /// Framing of a response to a request of the given `httparse` minor version.
+    pub const fn from_version(version: u8) -> Self {
+        if version == 0 {
+            Self::Close
+        } else {
+            Self::Chunked
+        }
+    }
+}
+
+/// The self-contained browser player, which renders the stream of the page's own path
+const PLAYER: &str = include_str!("player.html");
+
+/// How a listener talks to its clients
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// Verify an HTTP request before streaming
+    Http,
+    /// Stream frames as soon as the connection is accepted
+    Raw,
+    /// Negotiate the terminal over telnet before streaming
+    Telnet,
+    /// Establish an SSH session before streaming
+    #[cfg(feature = "ssh")]
+    Ssh,
+}
+
+/// What a verified request is answered with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// The terminal stream, over HTTP
+    Stream,
+    /// The HTML player, for browsers
+    Player,
+    /// Every frame as a JSON event, for `EventSource` and other consumers of Server-Sent Events
+    Events,
+    /// Every frame as a message of its own, over a WebSocket
+    WebSocket(Upgrade),
+    /// The raw terminal stream, without any HTTP
+    Raw,
+    /// The terminal stream over telnet, fitted to the size of the client's terminal
+    Telnet,
+    /// The terminal stream over SSH, fitted to the size of the client's terminal
+    #[cfg(feature = "ssh")]
+    Ssh,
+}
+
+/// A verified request for the stream of a channel
+pub struct Viewer {
+    pub addr: SocketAddr,
+    pub channel: Arc<Channel>,
+    pub options: Options,
+    pub framing: Framing,
+    pub encoding: Encoding,
+    pub response: Response,
+    /// Size of the client's terminal, which only telnet clients report
+    pub window: Option<Arc<Window>>,
+}
+
+impl Viewer {
+    /// The entire response of a request for the player.
+    pub fn player(&self) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+            Content-Type: text/html; charset=utf-8\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\
+            Cache-Control: no-store\r\n\r\n\
+            {}",
+            PLAYER.len(),
+            PLAYER
+        )
+    }
+
+    /// The head of the streaming response.
+    pub fn head(&self) -> String {
+        let (version, transfer_encoding) = match self.framing {
+            Framing::Chunked => ("1.1", "Transfer-Encoding: chunked\r\n"),
+            Framing::Close => ("1.0", ""),
+        };
+        let content_encoding = match self.encoding {
+            Encoding::Identity => String::new(),
+            e => format!("Content-Encoding: {}\r\n", e.name()),
+        };
+        let content_type = match self.response {
+            Response::Events => "text/event-stream",
+            _ => "text/plain; charset=utf-8",
+        };
+        format!(
+            "HTTP/{} 200 OK\r\n\
+            Content-Type: {}\r\n\
+            {}{}\
+            Vary: Accept-Encoding\r\n\
+            Connection: close\r\n\
+            Cache-Control: no-store\r\n\
+            X-Accel-Buffering: no\r\n\r\n",
+            version, content_type, transfer_encoding, content_encoding
+        )
+    }
+}
+
+/// The body of a streaming response, where everything written between two flushes is sent as a single chunk
+pub struct Body<W: Write> {
+    inner: W,
+    framing: Framing,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Body<W> {
+    pub const fn new(inner: W, framing: Framing) -> Self {
+        Self {
+            inner,
+            framing,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Send whatever is left, then mark the end of the body.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush()?;
+        if let Framing::Chunked = self.framing {
+            self.inner.write_all(b"0\r\n\r\n")?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Body<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            match self.framing {
+                Framing::Chunked => {
+                    // the whole chunk is sent at once to avoid any extra round trips
+                    let size = format!("{:x}\r\n", self.buffer.len());
+                    self.buffer.splice(0..0, size.bytes());
+                    self.buffer.extend_from_slice(b"\r\n");
+                    self.inner.write_all(&self.buffer)?;
+                }
+                Framing::Close => self.inner.write_all(&self.buffer)?,
+            }
+            self.buffer.clear();
+        }
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,317 @@
+use log::warn;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{Config, CounterGuard, Result};
+
+/// How often the addresses that no longer need to be tracked are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Fewest tracked addresses that have them forgotten before the interval is up
+const PRUNE_SIZE: usize = 4096;
+
+/// The limit that a connection exceeded
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+    /// Too many connections per second from the same address, along with the seconds until the next is allowed
+    Rate(u64),
+    /// Too many concurrent streams from the same address
+    PerIp(usize),
+    /// Too many concurrent streams overall
+    Global(usize),
+}
+
+impl Limit {
+    /// Seconds after which the client may try again.
+    pub const fn retry_after(&self) -> u64 {
+        match self {
+            Self::Rate(secs) => *secs,
+            Self::PerIp(_) | Self::Global(_) => 60,
+        }
+    }
+
+    /// Human-readable explanation of why the connection was refused.
+    pub fn explain(&self) -> String {
+        match self {
+            Self::Rate(secs) => format!(
+                "Too many connections from your address, try again in {}s.",
+                secs
+            ),
+            Self::PerIp(max) => format!(
+                "Your address is already watching {} streams, which is the limit.",
+                max
+            ),
+            Self::Global(max) => format!(
+                "The server is already serving {} streams, which is the limit.",
+                max
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&match self {
+            Self::Rate(_) => "connection rate".to_string(),
+            Self::PerIp(max) => format!("{} streams per address", max),
+            Self::Global(max) => format!("{} streams overall", max),
+        })
+    }
+}
+
+/// The connections of a single address
+struct Peer {
+    streams: usize,
+    tokens: f64,
+    updated: Instant,
+}
+
+struct LimitState {
+    streams: usize,
+    peers: HashMap<IpAddr, Peer>,
+    pruned: Instant,
+    prune_size: usize,
+}
+
+/// Connection limits shared by every listener
+pub struct Limiter {
+    max_streams: Option<usize>,
+    max_streams_per_ip: Option<usize>,
+    rate: Option<f64>,
+    burst: f64,
+    state: Mutex<LimitState>,
+    refused: AtomicUsize,
+    max_pending: usize,
+    pending: Arc<AtomicUsize>,
+}
+
+impl Limiter {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            max_streams: cfg.max_streams(),
+            max_streams_per_ip: cfg.max_streams_per_ip(),
+            rate: cfg.accept_rate(),
+            burst: f64::from(cfg.accept_burst()),
+            state: Mutex::new(LimitState {
+                streams: 0,
+                peers: HashMap::new(),
+                pruned: Instant::now(),
+                prune_size: PRUNE_SIZE,
+            }),
+            refused: AtomicUsize::new(0),
+            max_pending: cfg.max_pending(),
+            pending: Arc::default(),
+        }
+    }
+
+    /// Count a handshake as in progress for as long as the guard lives, unless too many already are on any listener.
+    pub fn begin_handshake(&self) -> Option<CounterGuard> {
+        CounterGuard::acquire(&self.pending, self.max_pending)
+    }
+
+    /// Count and log the refusal of a connection.
+    fn refuse(&self, ip: IpAddr, limit: Limit) -> Limit {
+        let refused = self.refused.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!("Refused {}: {} ({} refused so far)", ip, limit, refused);
+        limit
+    }
+
+    /// Tokens in the bucket of the address, as of now.
+    fn tokens(&self, peer: &Peer, now: Instant) -> f64 {
+        let refill = self.rate.map_or(0.0, |rate| {
+            now.duration_since(peer.updated).as_secs_f64() * rate
+        });
+        (peer.tokens + refill).min(self.burst)
+    }
+
+    /// Forget the addresses that have neither streams nor a partially empty bucket, once the interval is up
+    /// or twice as many are tracked as were kept the last time.
+    fn prune(&self, state: &mut LimitState, now: Instant) {
+        if now.duration_since(state.pruned) < PRUNE_INTERVAL && state.peers.len() < state.prune_size
+        {
+            return;
+        }
+        state
+            .peers
+            .retain(|_, p| p.streams > 0 || self.tokens(p, now) < self.burst);
+        state.pruned = now;
+        state.prune_size = PRUNE_SIZE.max(state.peers.len() * 2);
+    }
+
+    /// The state of the address, which begins with a full bucket.
+    fn peer<'a>(&self, state: &'a mut LimitState, ip: IpAddr, now: Instant) -> &'a mut Peer {
+        self.prune(state, now);
+        state.peers.entry(ip).or_insert(Peer {
+            streams: 0,
+            tokens: self.burst,
+            updated: now,
+        })
+    }
+
+    /// Take a token from the bucket of the address, which refills at the accept rate.
+    pub fn throttle(&self, ip: IpAddr) -> Result {
+        self.throttle_at(ip, Instant::now())
+    }
+
+    /// Take a token from the bucket of the address as of the given time.
+    fn throttle_at(&self, ip: IpAddr, now: Instant) -> Result {
+        let Some(rate) = self.rate else {
+            return Ok(());
+        };
+        let mut state = self.state.lock();
+
+        let peer = self.peer(&mut state, ip, now);
+        peer.tokens = self.tokens(peer, now);
+        peer.updated = now;
+
+        if peer.tokens < 1.0 {
+            let secs = ((1.0 - peer.tokens) / rate).ceil() as u64;
+            return Err(self.refuse(ip, Limit::Rate(secs)).into());
+        }
+        peer.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Reserve a stream for the address, which is released once the [`Permit`] is dropped.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Result<Permit> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+
+        if let Some(max) = self.max_streams.filter(|max| state.streams >= *max) {
+            return Err(self.refuse(ip, Limit::Global(max)).into());
+        }
+
+        // the streams of each address are only counted if they are limited
+        if let Some(max) = self.max_streams_per_ip {
+            let peer = self.peer(&mut state, ip, now);
+            if peer.streams >= max {
+                return Err(self.refuse(ip, Limit::PerIp(max)).into());
+            }
+            peer.streams += 1;
+        }
+        state.streams += 1;
+
+        Ok(Permit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// A stream reserved by [`Limiter::acquire`]
+pub struct Permit {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock();
+        state.streams -= 1;
+        if self.limiter.max_streams_per_ip.is_some() {
+            if let Some(peer) = state.peers.get_mut(&self.ip) {
+                peer.streams -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::base::{Error, InitConfig};
+
+    const A: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const B: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn limiter(args: &[&str]) -> Arc<Limiter> {
+        let args = ["donut-live"].iter().chain(args);
+        Arc::new(Limiter::new(
+            &Config::from_init(InitConfig::parse_from(args)).unwrap(),
+        ))
+    }
+
+    /// The limit the result exceeded, if any.
+    fn limit<T>(res: Result<T>) -> Option<Limit> {
+        match res {
+            Err(Error::Limit(limit)) => Some(limit),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limiter = limiter(&["--accept-rate", "2", "--accept-burst", "3"]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // a burst empties the bucket, which then refills at the rate
+        for _ in 0..3 {
+            assert!(limiter.throttle_at(A, start).is_ok());
+        }
+        assert!(matches!(
+            limit(limiter.throttle_at(A, at(0))),
+            Some(Limit::Rate(1))
+        ));
+        assert!(limit(limiter.throttle_at(A, at(400))).is_some());
+        assert!(limiter.throttle_at(A, at(500)).is_ok());
+        assert!(limit(limiter.throttle_at(A, at(600))).is_some());
+
+        // every address has a bucket of its own
+        assert!(limiter.throttle_at(B, at(600)).is_ok());
+
+        // which never holds more than the burst, however long it refills
+        let later = at(60_000);
+        for _ in 0..3 {
+            assert!(limiter.throttle_at(A, later).is_ok());
+        }
+        assert!(limit(limiter.throttle_at(A, later)).is_some());
+
+        // a slower rate asks to wait longer
+        let limiter = self::limiter(&["--accept-rate", "0.1", "--accept-burst", "1"]);
+        assert!(limiter.throttle_at(A, start).is_ok());
+        assert!(matches!(
+            limit(limiter.throttle_at(A, start)),
+            Some(Limit::Rate(10))
+        ));
+    }
+
+    #[test]
+    fn unthrottled() {
+        let limiter = limiter(&[]);
+        assert!((0..1000).all(|_| limiter.throttle(A).is_ok()));
+    }
+
+    #[test]
+    fn stream_caps() {
+        let limiter = limiter(&["--max-streams", "3", "--max-streams-per-ip", "2"]);
+        let a = [limiter.acquire(A).ok(), limiter.acquire(A).ok()];
+        assert!(matches!(limit(limiter.acquire(A)), Some(Limit::PerIp(2))));
+
+        // another address only runs into the global cap
+        let b = limiter.acquire(B).ok();
+        assert!(a.iter().chain([&b]).all(Option::is_some));
+        assert!(matches!(limit(limiter.acquire(B)), Some(Limit::Global(3))));
+
+        // every permit gives its stream back once dropped
+        drop(b);
+        let b = limiter.acquire(B).ok();
+        assert!(b.is_some());
+        drop(a);
+        assert!(limiter.acquire(A).is_ok());
+        let _a = [limiter.acquire(A).ok(), limiter.acquire(A).ok()];
+        assert_eq!(limiter.state.lock().streams, 3);
+        assert_eq!(limiter.state.lock().peers[&A].streams, 2);
+    }
+}
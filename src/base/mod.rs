@@ -1,22 +1,58 @@
+mod access;
+mod agent;
 mod ascii;
 mod cfg;
+mod channel;
+mod cidr;
 mod client;
 mod donut;
+mod encoding;
 mod err;
+mod events;
 mod frame;
+mod http;
+mod limit;
+mod playback;
 mod progress;
+mod proxy;
+mod sched;
+mod stream;
 mod sync;
+mod systemd;
+mod telnet;
 mod util;
+mod websocket;
 
+pub use access::*;
+pub use agent::*;
 pub use ascii::*;
 pub use cfg::*;
+pub use channel::*;
+pub use cidr::*;
 pub use client::*;
+pub use encoding::*;
 pub use err::*;
+pub use events::*;
 pub use frame::*;
+pub use http::*;
+pub use limit::*;
 use onebuck::{Bucket, ValueIndex};
+pub use playback::*;
 pub use progress::*;
+pub use proxy::*;
+pub use sched::*;
+pub use stream::*;
 pub use sync::*;
+pub use systemd::*;
+pub use telnet::*;
 pub use util::*;
+pub use websocket::*;
+
+#[cfg(unix)]
+mod privilege;
+
+#[cfg(unix)]
+pub use privilege::*;
 
 #[cfg(feature = "logger")]
 mod logger;
@@ -24,21 +60,37 @@ mod logger;
 #[cfg(feature = "logger")]
 pub use logger::*;
 
+#[cfg(feature = "tls")]
+mod tls;
+
+#[cfg(feature = "tls")]
+pub use tls::*;
+
+#[cfg(feature = "ssh")]
+mod ssh;
+
+#[cfg(feature = "ssh")]
+pub use ssh::*;
+
+use log::{debug, info, warn};
 use std::{
     io::Write,
-    net::TcpListener,
-    thread::{sleep, JoinHandle},
+    net::SocketAddr,
+    sync::Arc,
+    thread::{spawn, JoinHandle},
+    time::{Duration, Instant},
 };
 
-/// The initial HTTP response headers appended with the `ESC[2J` erase function
-const INIT: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n\x1b[2J";
+/// How long every thread has to finish once the connections of a shutdown are closed
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Automatically remove any disconnected clients.
 pub fn error_handler(
+    shutdown: &Shutdown,
     streams: SignalLock<Bucket<Client>>,
     disconnected: SignalLock<Vec<ValueIndex>>,
 ) -> JoinHandle<Result> {
-    init_handler(move || {
+    init_handler(shutdown, move || {
         // wait for a connection to be lost
         disconnected.wait();
 
@@ -63,41 +115,193 @@ pub fn error_handler(
     })
 }
 
-/// Validate and instantiate streams into the system.
-pub fn incoming_handler(
-    server: TcpListener,
-    streams: SignalLock<Bucket<Client>>,
-    path: String,
-) -> JoinHandle<Result> {
-    init_handler(move || {
-        // handle any potential stream waiting to be accepted by the server
-        let (mut stream, ..) = server.accept()?;
+/// Let the client know which limit it exceeded, if any, before the connection is dropped.
+fn refuse<T>(mut stream: &Stream, response: Response, res: Result<T>) -> Result<T> {
+    if let Err(Error::Limit(limit)) = &res {
+        match response {
+            Response::Raw => writeln!(stream, "{}", limit.explain())?,
+            Response::Telnet => write!(stream, "{}\r\n", limit.explain())?,
+            #[cfg(feature = "ssh")]
+            Response::Ssh => write!(stream, "{}\r\n", limit.explain())?,
+            _ => reject(
+                stream,
+                "429 Too Many Requests",
+                &format!("Retry-After: {}\r\n", limit.retry_after()),
+                &limit.explain(),
+            )?,
+        }
+    }
+    res
+}
+
+/// Everything the streams of a listener are verified against
+struct Gate {
+    mounts: Arc<[Mount]>,
+    cfg: Arc<Config>,
+    access: Arc<Access>,
+    limiter: Arc<Limiter>,
+    protocol: Protocol,
+    /// Whether the connections come through a load balancer
+    is_proxied: bool,
+    shutdown: Shutdown,
+}
+
+impl Gate {
+    /// Determinant for whether the address may connect, where the Unix domain socket doesn't have one to check.
+    fn permits(&self, addr: SocketAddr) -> bool {
+        addr == UNIX_PEER || self.access.permits(addr.ip())
+    }
+}
+
+/// Verify a newly accepted stream, then instantiate it into the system.
+fn handshake(incoming: Incoming, peer: SocketAddr, gate: &Gate) -> Result {
+    let Gate {
+        mounts,
+        cfg,
+        limiter,
+        protocol,
+        ..
+    } = gate;
+
+    // bound how long the peer has to complete the handshake, however it spreads out what it sends
+    let deadline = Instant::now() + cfg.handshake_timeout();
+    incoming.set_timeouts(Some(cfg.handshake_timeout()))?;
+
+    // the load balancer in front knows the actual peer, which it tells ahead of any TLS or SSH handshake
+    let peer = match gate.is_proxied {
+        true => proxied_addr(Timed::new(&incoming, deadline), peer, cfg.trusted_proxies())?,
+        false => peer,
+    };
+    if !gate.permits(peer) {
+        debug!("Dropped {}: not allowed", peer);
+        return Ok(());
+    }
+    let mut stream = incoming.begin(deadline)?;
+
+    // determine the authenticity of the stream, the channel it wants and how it wants it
+    let viewer = match *protocol {
+        Protocol::Http => verify_stream(&stream, peer, mounts, cfg, deadline)?,
+        Protocol::Raw => raw_viewer(peer, mounts, cfg)?,
+        Protocol::Telnet => telnet_viewer(&stream, peer, mounts, cfg, deadline)?,
+        #[cfg(feature = "ssh")]
+        Protocol::Ssh => ssh_viewer(&stream, peer, mounts, cfg, deadline)?,
+    };
+
+    // the clients of a trusted proxy are allowed or not by their own address
+    if viewer.addr != peer && !gate.permits(viewer.addr) {
+        debug!("Dropped {}: not allowed", viewer.addr);
+        return Ok(());
+    }
+
+    // refuse addresses that connect too often, which proxies only tell apart once the request is read
+    let ip = viewer.addr.ip();
+    refuse(&stream, viewer.response, limiter.throttle(ip))?;
+
+    // browsers only need the player, which then requests the stream itself
+    if viewer.response == Response::Player {
+        stream.write_all(viewer.player().as_bytes())?;
+        debug!("Served the player to {}", viewer.addr);
+        return Ok(());
+    }
+
+    // refuse addresses that are already watching too many streams, or any address if the server is full
+    let permit = refuse(&stream, viewer.response, limiter.acquire(ip))?;
+
+    // begin the streaming response
+    match viewer.response {
+        Response::Stream | Response::Events => stream.write_all(viewer.head().as_bytes())?,
+        Response::WebSocket(upgrade) => stream.write_all(upgrade.head().as_bytes())?,
+        _ => {}
+    }
+
+    // the handshake is complete, so lift the deadlines
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+
+    // hand the stream over to its own writer
+    #[cfg(feature = "logger")]
+    let addr = viewer.addr;
+    let channel = viewer.channel.clone();
+    let client = Client::new(stream, viewer, permit, cfg.queue_size(), cfg.max_dropped())?;
+
+    // add the client to the map of its channel, where a handshake that completes once the server is shutting down
+    // has missed the goodbye of every other client
+    let streams = channel.streams();
+    let mut clients = streams.write();
+    if gate.shutdown.is_begun() {
+        client.farewell(cfg.goodbye())
+    }
+    clients.insert(client);
+    drop(clients);
+
+    // notify `streams` of a new connection
+    *streams.lock() = true;
+    streams.notify();
 
-        // determine the authenticity of the stream
-        let addr = verify_stream(&stream, &path)?;
+    #[cfg(feature = "logger")]
+    log_to_file(addr)?;
+
+    Ok(())
+}
 
-        // setup the client's terminal
-        stream.write_all(INIT)?;
+/// Accept incoming streams, verifying each one on its own thread.
+pub fn incoming_handler(
+    server: Server,
+    mounts: Arc<[Mount]>,
+    cfg: Arc<Config>,
+    access: Arc<Access>,
+    limiter: Arc<Limiter>,
+    protocol: Protocol,
+    shutdown: &Shutdown,
+) -> JoinHandle<Result> {
+    // connections of this listener come through a load balancer, if so configured for its port or the Unix domain socket
+    let is_proxied = server.port().is_ok_and(|port| cfg.is_proxied(port));
+    let gate = Arc::new(Gate {
+        mounts,
+        cfg,
+        access,
+        limiter,
+        protocol,
+        is_proxied,
+        shutdown: shutdown.clone(),
+    });
 
-        // add the stream to the map
-        streams.write().insert(Client::new(stream, addr));
+    init_handler(shutdown, move || {
+        // handle any potential stream waiting to be accepted by the server, unless the server is no longer accepting anyone
+        let (incoming, peer) = match server.accept() {
+            _ if gate.shutdown.is_begun() => return Ok(()),
+            res => res?,
+        };
 
-        // notify `streams` of a new connection
-        *streams.lock() = true;
-        streams.notify();
+        // refuse the stream before reading anything if its address is not allowed
+        if !gate.permits(peer) {
+            debug!("Dropped {}: not allowed", peer);
+            return Ok(());
+        }
 
-        #[cfg(feature = "logger")]
-        log_to_file(addr)?;
+        // refuse the stream if too many handshakes are already in progress on any listener
+        let Some(guard) = gate.limiter.begin_handshake() else {
+            warn!("Dropped {}: too many pending handshakes", peer);
+            return Ok(());
+        };
 
+        // slow or silent peers only ever stall their own thread
+        let gate = gate.clone();
+        spawn(move || {
+            let _guard = guard;
+            if let Err(e) = handshake(incoming, peer, &gate) {
+                debug!("Dropped {}: {}", peer, e)
+            }
+        });
         Ok(())
     })
 }
 
-/// Distribute each frame to every stream.
+/// Queue the frame for every stream.
 pub fn _dist_handler(
     streams: &SignalLock<Bucket<Client>>,
     disconnected: &SignalLock<Vec<ValueIndex>>,
-    frame: &AsciiFrame,
+    frame_index: usize,
 ) -> Result {
     // discontinue distributing frames and pause
     // this thread if there are no connections
@@ -113,15 +317,15 @@ pub fn _dist_handler(
         // // which is covered as this guard gets automatically dropped
         let mut g = disconnected.write();
 
-        // send each stream the current frame
-        for mut client in streams.read().iter() {
-            // remove the client if they have disconnected
-            if client.write_all(frame.as_ref()).is_err() {
+        // queue the current frame for each stream, which never blocks
+        for client in streams.read().iter() {
+            // remove the client if they have disconnected or fallen too far behind
+            if !client.send(frame_index) && client.disconnect() {
                 g.push(client.into())
             }
         }
         // determinant for whether there have been any disconnections
-        g.len() > 0
+        !g.is_empty()
     };
 
     // notify `disconnected` due to a disconnection
@@ -132,26 +336,106 @@ pub fn _dist_handler(
     Ok(())
 }
 
-/// Distribute each frame to every stream.
+/// Distribute each frame to every stream, according to the schedule.
 pub fn dist_handler(
+    shutdown: &Shutdown,
     streams: &SignalLock<Bucket<Client>>,
     disconnected: &SignalLock<Vec<ValueIndex>>,
     frames: &[AsciiFrame],
-    frame_index: &mut usize,
+    scheduler: &mut Scheduler,
+    heartbeat: &Heartbeat,
 ) -> Result {
     // wait until there's at least one connection
+    heartbeat.rest();
     streams.wait();
 
-    // distribute the frames to each client
-    for (i, frame) in frames.iter().enumerate().skip(*frame_index) {
-        if let Err(e) = _dist_handler(streams, disconnected, frame) {
-            *frame_index = i;
+    // distribute the frames to each client until the animation wraps around, or the server shuts down
+    while !shutdown.is_begun() {
+        heartbeat.beat();
+        if let Err(e) = _dist_handler(streams, disconnected, scheduler.index()) {
+            scheduler.pause();
             return Err(e);
         }
-        // the delay of the current frame
-        sleep(frame.delay())
+        // wait for the deadline of the next frame
+        if scheduler.advance(frames) {
+            return Ok(());
+        }
     }
-    // reset frame index
-    *frame_index = 0;
     Ok(())
 }
+
+/// Stop accepting, then let every client restore its terminal and read the goodbye message before its connection is closed.
+/// Every thread is then joined, where those that don't finish in time are left behind.
+pub fn shut_down(
+    shutdown: &Shutdown,
+    servers: &[Server],
+    channels: &[Arc<Channel>],
+    handles: Vec<JoinHandle<Result>>,
+    cfg: &Config,
+) {
+    let deadline = Instant::now() + cfg.shutdown_timeout();
+    shutdown.begin();
+
+    // wake up every listener, which then stops
+    for server in servers {
+        if let Err(e) = server.close() {
+            debug!("{}", e)
+        }
+    }
+
+    for channel in channels {
+        for client in channel.streams().read().iter() {
+            client.farewell(cfg.goodbye())
+        }
+        // wake up the distributor and the remover of the channel, which then stop
+        *channel.streams().lock() = true;
+        channel.streams().notify();
+        *channel.disconnected().lock() = true;
+        channel.disconnected().notify();
+    }
+
+    // give every writer until the deadline to end its stream
+    let is_said = || {
+        channels
+            .iter()
+            .all(|c| c.streams().read().iter().all(|c| c.is_finished()))
+    };
+    if !wait_until(deadline, is_said) {
+        warn!(
+            "Closing the streams that didn't end within {:?}",
+            cfg.shutdown_timeout()
+        );
+    }
+
+    // close every connection, which also stops any writer still blocked on a slow client
+    for channel in channels {
+        channel.streams().read().iter().for_each(|c| c.close());
+    }
+
+    let deadline = Instant::now() + CLOSE_TIMEOUT;
+    wait_until(deadline, || {
+        handles.iter().all(JoinHandle::is_finished) && is_said()
+    });
+    let writers = channels
+        .iter()
+        .map(|c| {
+            c.streams()
+                .read()
+                .iter()
+                .filter(|c| !c.is_finished())
+                .count()
+        })
+        .sum::<usize>();
+    let remaining = writers + handles.iter().filter(|h| !h.is_finished()).count();
+    for handle in handles.into_iter().filter(JoinHandle::is_finished) {
+        match handle.join() {
+            Ok(Err(e)) => debug!("{}", e),
+            Err(_) => warn!("{}", Error::Sync),
+            Ok(Ok(())) => {}
+        }
+    }
+    match remaining {
+        0 => info!("Shut down"),
+        n => warn!("Shut down, leaving {} threads behind", n),
+    }
+}
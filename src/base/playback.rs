@@ -0,0 +1,451 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use super::{AsciiFrame, UriError};
+
+/// The offset of every frame from the start of the animation
+pub struct Timeline {
+    offsets: Vec<Duration>,
+    total: Duration,
+}
+
+impl Timeline {
+    pub fn new(frames: &[AsciiFrame]) -> Self {
+        let mut total = Duration::ZERO;
+        let offsets = frames
+            .iter()
+            .map(|f| {
+                let offset = total;
+                total += f.delay();
+                offset
+            })
+            .collect();
+        Self { offsets, total }
+    }
+
+    /// Number of frames.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Length of a single loop of the animation.
+    pub const fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Offset of the frame at the given index.
+    pub fn offset(&self, index: usize) -> Duration {
+        self.offsets[index]
+    }
+
+    /// Index of the frame shown at the given offset, wrapping around the end of the animation.
+    pub fn index_at(&self, offset: Duration) -> usize {
+        let offset = Duration::from_nanos((offset.as_nanos() % self.total.as_nanos()) as u64);
+        self.offsets.partition_point(|o| *o <= offset) - 1
+    }
+
+    /// Playback time between two frames, moving forward only.
+    pub fn distance(&self, from: usize, to: usize) -> Duration {
+        let (from, to) = (self.offset(from), self.offset(to));
+        if to >= from {
+            to - from
+        } else {
+            self.total - from + to
+        }
+    }
+}
+
+/// Parse the value of a boolean option, where a bare key means `true`.
+fn parse_bool(key: &str, value: Option<&str>) -> Result<bool, UriError> {
+    match value {
+        None | Some("1" | "on" | "true" | "yes") => Ok(true),
+        Some("0" | "off" | "false" | "no") => Ok(false),
+        Some(v) => Err(UriError::Query(format!(
+            "{}={} is invalid, expected on or off",
+            key, v
+        ))),
+    }
+}
+
+/// The error of an option that isn't a positive number.
+fn invalid_num(key: &str, value: Option<&str>) -> UriError {
+    UriError::Query(format!(
+        "{}={} is invalid, expected a positive number",
+        key,
+        value.unwrap_or_default()
+    ))
+}
+
+/// Parse the value of a positive number option.
+fn parse_num<T: FromStr + PartialOrd + Default>(
+    key: &str,
+    value: Option<&str>,
+) -> Result<T, UriError> {
+    value
+        .and_then(|v| T::from_str(v).ok())
+        .filter(|n| *n > T::default())
+        .ok_or_else(|| invalid_num(key, value))
+}
+
+/// Parse the value of a positive number option, where infinity is no number either.
+fn parse_float(key: &str, value: Option<&str>) -> Result<f32, UriError> {
+    parse_num::<f32>(key, value)
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| invalid_num(key, value))
+}
+
+/// Playback options of a single client, provided through the query string
+#[derive(Clone, Debug)]
+pub struct Options {
+    speed: f32,
+    loops: Option<u32>,
+    duration: Option<Duration>,
+    fps: Option<f32>,
+    colored: bool,
+    reverse: bool,
+    resume: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            loops: None,
+            duration: None,
+            fps: None,
+            colored: true,
+            reverse: false,
+            resume: None,
+        }
+    }
+}
+
+impl Options {
+    /// Options that end playback after the given number of loops or amount of time, if any.
+    pub fn finite(loops: Option<u32>, duration: Option<Duration>) -> Self {
+        Self {
+            loops,
+            duration,
+            ..Default::default()
+        }
+    }
+
+    /// Resume playback right after the frame at the given index, which a reconnecting client received last.
+    pub const fn resuming_after(self, index: usize) -> Self {
+        Self {
+            resume: Some(index),
+            ..self
+        }
+    }
+
+    /// Override the options with those of a query string such as `speed=2&loops=3&color=off&reverse`.
+    pub fn with_query(self, query: &str) -> Result<Self, UriError> {
+        let mut options = self;
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (pair, None),
+            };
+            match key {
+                "speed" => options.speed = parse_float(key, value)?.min(100.0),
+                "loops" => options.loops = Some(parse_num(key, value)?),
+                "duration" => {
+                    let secs = parse_float(key, value)?;
+                    let duration = Duration::try_from_secs_f32(secs);
+                    options.duration = Some(duration.map_err(|_| invalid_num(key, value))?)
+                }
+                "fps" => options.fps = Some(parse_float(key, value)?),
+                "color" => options.colored = parse_bool(key, value)?,
+                "reverse" => options.reverse = parse_bool(key, value)?,
+                _ => {
+                    return Err(UriError::Query(format!(
+                        "unknown option {}, expected speed, loops, duration, fps, color or reverse",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// What a client is told once the server shuts down, shared between the client and the playback of its writer
+#[derive(Clone, Default)]
+pub struct Farewell(Arc<OnceLock<String>>);
+
+impl Farewell {
+    /// End playback, leaving the client with the given message.
+    pub fn say(&self, message: &str) {
+        _ = self.0.set(message.to_owned())
+    }
+
+    /// The message the client is left with, once the farewell has been said.
+    pub fn message(&self) -> Option<&str> {
+        self.0.get().map(String::as_str)
+    }
+}
+
+/// What a client should do upon receiving a frame from its channel
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Send the frame at the given index
+    Frame(usize),
+    /// Send nothing this time around
+    Skip,
+    /// Playback has finished
+    End,
+}
+
+/// Maps the frames of a channel onto the frames a client has asked for
+pub struct Playback {
+    options: Options,
+    timeline: Arc<Timeline>,
+    start: Duration,
+    played: Duration,
+    channel_index: Option<usize>,
+    frame_index: Option<usize>,
+    started_at: Option<Instant>,
+    sent_at: Option<Instant>,
+    farewell: Farewell,
+}
+
+impl Playback {
+    pub const fn new(options: Options, timeline: Arc<Timeline>, farewell: Farewell) -> Self {
+        Self {
+            options,
+            timeline,
+            farewell,
+            start: Duration::ZERO,
+            played: Duration::ZERO,
+            channel_index: None,
+            frame_index: None,
+            started_at: None,
+            sent_at: None,
+        }
+    }
+
+    /// Whether the frames should be sent with their colors.
+    pub const fn is_colored(&self) -> bool {
+        self.options.colored
+    }
+
+    /// The message the client is left with, if the server is shutting down.
+    pub fn farewell(&self) -> Option<&str> {
+        self.farewell.message()
+    }
+
+    /// Follow the channel onto the given frame.
+    pub fn advance(&mut self, channel_index: usize) -> Step {
+        // the server is shutting down
+        if self.farewell().is_some() {
+            return Step::End;
+        }
+
+        // playback starts wherever the channel currently is, unless a reconnecting client resumes where it left off
+        match self.channel_index.replace(channel_index) {
+            Some(prev) => {
+                // rounded to the microsecond, as `mul_f32` falls just short of whole loops even at normal speed
+                let delta = self.timeline.distance(prev, channel_index).as_micros() as f64;
+                self.played +=
+                    Duration::from_micros((delta * f64::from(self.options.speed)).round() as u64);
+            }
+            None => {
+                let len = self.timeline.len();
+                let start = match self.options.resume {
+                    // the frame that follows the last one received, in the direction of playback
+                    Some(last) if self.options.reverse => (len - last % len) % len,
+                    Some(last) => (last % len + 1) % len,
+                    None => channel_index,
+                };
+                self.start = self.timeline.offset(start);
+                self.started_at = Some(Instant::now());
+            }
+        }
+
+        // stop once every requested loop has been played
+        if let Some(loops) = self.options.loops {
+            if self.played >= self.timeline.total() * loops {
+                return Step::End;
+            }
+        }
+
+        // stop once the requested amount of time has passed
+        if let (Some(duration), Some(started_at)) = (self.options.duration, self.started_at) {
+            if started_at.elapsed() >= duration {
+                return Step::End;
+            }
+        }
+
+        let mut index = self.timeline.index_at(self.start + self.played);
+        if self.options.reverse {
+            index = self.timeline.len() - 1 - index;
+        }
+
+        // the frame is already on screen
+        if self.frame_index == Some(index) {
+            return Step::Skip;
+        }
+
+        // the frame would exceed the requested frame rate
+        if let (Some(fps), Some(sent_at)) = (self.options.fps, self.sent_at) {
+            if sent_at.elapsed().as_secs_f32() < 1.0 / fps {
+                return Step::Skip;
+            }
+        }
+
+        self.frame_index = Some(index);
+        self.sent_at = Some(Instant::now());
+        Step::Frame(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A timeline of frames lasting 10, 20 and 30 milliseconds.
+    fn timeline() -> Arc<Timeline> {
+        let frames = [10, 20, 30].map(|ms| AsciiFrame::new(Vec::new(), Duration::from_millis(ms)));
+        Arc::new(Timeline::new(&frames))
+    }
+
+    /// The steps of a client following the channel across the given frames.
+    fn steps(options: Options, channel: &[usize]) -> Vec<Step> {
+        let mut playback = Playback::new(options, timeline(), Farewell::default());
+        channel.iter().map(|i| playback.advance(*i)).collect()
+    }
+
+    fn query(query: &str) -> Options {
+        Options::default().with_query(query).ok().unwrap()
+    }
+
+    #[test]
+    fn offsets() {
+        let timeline = timeline();
+        assert_eq!(timeline.total(), Duration::from_millis(60));
+        for (ms, index) in [
+            (0, 0),
+            (9, 0),
+            (10, 1),
+            (29, 1),
+            (30, 2),
+            (59, 2),
+            (60, 0),
+            (130, 1),
+        ] {
+            assert_eq!(
+                timeline.index_at(Duration::from_millis(ms)),
+                index,
+                "{}",
+                ms
+            );
+        }
+        for (from, to, ms) in [(0, 2, 30), (2, 0, 30), (2, 1, 40), (1, 1, 0)] {
+            assert_eq!(timeline.distance(from, to), Duration::from_millis(ms));
+        }
+    }
+
+    #[test]
+    fn loops() {
+        use Step::*;
+        assert_eq!(
+            steps(query("loops=1"), &[0, 1, 2, 0]),
+            [Frame(0), Frame(1), Frame(2), End]
+        );
+        // playback starts wherever the channel is, and counts a loop from there
+        assert_eq!(
+            steps(query("loops=2"), &[1, 2, 0, 1, 2, 0, 1]),
+            [
+                Frame(1),
+                Frame(2),
+                Frame(0),
+                Frame(1),
+                Frame(2),
+                Frame(0),
+                End
+            ]
+        );
+        // twice as fast plays a loop in half the time
+        assert_eq!(
+            steps(query("loops=1&speed=2"), &[0, 1, 2]),
+            [Frame(0), Frame(1), End]
+        );
+        // half as fast shows a frame for as long as it takes
+        assert_eq!(
+            steps(query("speed=0.5"), &[0, 1, 2, 0]),
+            [Frame(0), Skip, Frame(1), Frame(2)]
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        use Step::*;
+        assert_eq!(
+            steps(query("reverse&loops=1"), &[0, 1, 2, 0]),
+            [Frame(2), Frame(1), Frame(0), End]
+        );
+        assert_eq!(
+            steps(query("reverse"), &[1, 2, 0]),
+            [Frame(1), Frame(0), Frame(2)]
+        );
+    }
+
+    #[test]
+    fn resume() {
+        use Step::*;
+        // the frame after the last one received, regardless of the channel, which still sets the pace
+        let options = query("loops=1").resuming_after(0);
+        assert_eq!(
+            steps(options, &[2, 0, 1, 2]),
+            [Frame(1), Frame(2), Skip, End]
+        );
+        // which comes before it in reverse
+        let options = query("reverse").resuming_after(1);
+        assert_eq!(steps(options, &[2, 0]), [Frame(0), Frame(2)]);
+    }
+
+    #[test]
+    fn resume_boundaries() {
+        use Step::*;
+        // the first frame, the last one, which wraps around, and anything past the end, which wraps as well
+        for (last, first) in [(0, 1), (2, 0), (3, 1), (5, 0), (usize::MAX, 1)] {
+            let options = Options::default().resuming_after(last);
+            assert_eq!(steps(options, &[0]), [Frame(first)], "{}", last);
+        }
+        // in reverse, the first frame wraps around instead
+        for (last, first) in [(0, 2), (2, 1), (3, 2)] {
+            let options = query("reverse").resuming_after(last);
+            assert_eq!(steps(options, &[0]), [Frame(first)], "{}", last);
+        }
+    }
+
+    #[test]
+    fn invalid_options() {
+        for query in [
+            "duration=inf",
+            "duration=NaN",
+            "duration=1e30",
+            "duration=-1",
+            "duration=0",
+            "speed=inf",
+            "speed=-inf",
+            "fps=infinity",
+            "loops=0",
+            "loops=1.5",
+            "color=maybe",
+            "volume=11",
+        ] {
+            assert!(Options::default().with_query(query).is_err(), "{}", query);
+        }
+        assert_eq!(
+            query("duration=1.5").duration,
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(query("speed=1000").speed, 100.0);
+    }
+}
@@ -0,0 +1,163 @@
+use libc::{c_int, gid_t, uid_t};
+use log::info;
+use std::{
+    env::{current_dir, set_current_dir},
+    ffi::CString,
+    io::{Error, ErrorKind},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path},
+};
+
+use super::{Config, Result, Route};
+
+/// Turn the return value of a libc call into the error it indicates, if any.
+fn check(ret: c_int) -> Result {
+    if ret == -1 {
+        Err(Error::last_os_error().into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Error of a user or group that doesn't exist.
+fn unknown(kind: &str, name: &str) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("{} {} does not exist", kind, name),
+    )
+}
+
+/// Id of the given user along with its primary group, where an id without any entry has no primary group.
+fn lookup_user(name: &str) -> Result<(uid_t, Option<gid_t>)> {
+    let id = name.parse::<uid_t>().ok();
+    let c_name = CString::new(name).map_err(|_| unknown("user", name))?;
+
+    // SAFETY: the entry is copied right away, while nothing else looks up any users
+    let entry = unsafe {
+        match id {
+            Some(uid) => libc::getpwuid(uid),
+            None => libc::getpwnam(c_name.as_ptr()),
+        }
+        .as_ref()
+        .map(|pw| (pw.pw_uid, pw.pw_gid))
+    };
+    match (entry, id) {
+        (Some((uid, gid)), _) => Ok((uid, Some(gid))),
+        (None, Some(uid)) => Ok((uid, None)),
+        (None, None) => Err(unknown("user", name).into()),
+    }
+}
+
+/// Id of the given group.
+fn lookup_group(name: &str) -> Result<gid_t> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c_name = CString::new(name).map_err(|_| unknown("group", name))?;
+
+    // SAFETY: the entry is copied right away, while nothing else looks up any groups
+    let entry = unsafe { libc::getgrnam(c_name.as_ptr()).as_ref().map(|gr| gr.gr_gid) };
+    entry.ok_or_else(|| unknown("group", name).into())
+}
+
+/// Make sure every file read once confined, i.e. the GIFs, the access file and the TLS certificate and key,
+/// is given relative to the working directory and lies within it.
+fn check_confined(cfg: &Config, dir: &Path) -> Result {
+    #[allow(unused_mut)]
+    let mut paths = cfg
+        .routes()
+        .iter()
+        .filter_map(Route::gif)
+        .chain(cfg.access_file())
+        .collect::<Vec<_>>();
+    #[cfg(feature = "tls")]
+    if let Some((_, cert, key)) = cfg.tls() {
+        paths.extend([cert, key])
+    }
+
+    for path in paths {
+        let resolved = path
+            .canonicalize()
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        // `..` climbs out of the working directory up to the root, but no further once it is the root
+        let is_within = path.is_relative() && !path.components().any(|c| c == Component::ParentDir);
+        if !is_within || !resolved.starts_with(dir) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} has to be relative to and within {} with --chroot",
+                    path.display(),
+                    dir.display()
+                ),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Confine the process to the working directory and switch to `--user` and `--group`, if specified,
+/// refusing to go on with any privileges left behind.
+pub fn drop_privileges(cfg: &Config) -> Result {
+    // users and groups can't be looked up anymore once confined
+    let user = cfg.user().map(lookup_user).transpose()?;
+    let group = match (cfg.group(), user) {
+        (Some(group), _) => Some(lookup_group(group)?),
+        (None, Some((_, Some(gid)))) => Some(gid),
+        (None, Some((uid, None))) => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("user {} has no primary group, expected --group", uid),
+            )
+            .into())
+        }
+        (None, None) => None,
+    };
+
+    // the working directory becomes the root, as it holds the cached frames
+    if cfg.is_chrooted() {
+        let dir = current_dir()?;
+        check_confined(cfg, &dir)?;
+        let path = CString::new(dir.as_os_str().as_bytes()).map_err(Error::from)?;
+        // SAFETY: the path is a valid C string
+        check(unsafe { libc::chroot(path.as_ptr()) })?;
+        set_current_dir("/")?;
+        info!("Confined to {}", dir.display());
+    }
+
+    // the group goes first, as changing it requires the privileges of root
+    if let Some(gid) = group {
+        // SAFETY: the list of supplementary groups only consists of the given group, which replaces those of root
+        check(unsafe { libc::setgroups(1, &gid) })?;
+        check(unsafe { libc::setgid(gid) })?;
+    }
+    if let Some((uid, _)) = user {
+        check(unsafe { libc::setuid(uid) })?;
+    }
+
+    // SAFETY: retrieving the ids of the process has no preconditions
+    let (uid, euid, gid, egid) = unsafe {
+        (
+            libc::getuid(),
+            libc::geteuid(),
+            libc::getgid(),
+            libc::getegid(),
+        )
+    };
+    let dropped = user.is_none_or(|(u, _)| uid == u && euid == u)
+        && group.is_none_or(|g| gid == g && egid == g)
+        // root must not be regained
+        && (uid == 0 || unsafe { libc::setuid(0) } == -1);
+    if !dropped {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "the privileges could not be dropped",
+        )
+        .into());
+    }
+
+    if user.is_some() || group.is_some() {
+        info!("Running as uid {} and gid {}", uid, gid);
+    }
+    Ok(())
+}
@@ -0,0 +1,475 @@
+use httparse::Header;
+use log::debug;
+use std::{
+    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use super::{Cidr, Invalid, Result, UriError, UNIX_PEER};
+
+/// Signature that begins every PROXY protocol v2 header
+const SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest PROXY protocol v1 line, excluding the leading `PROXY`
+const MAX_LINE: usize = 102;
+
+/// Read the PROXY protocol header that precedes anything else the load balancer sends,
+/// returning the address of the client it conveys, if any.
+fn read_proxy_header(mut stream: impl Read) -> Result<Option<SocketAddr>> {
+    let mut start = [0; 5];
+    stream.read_exact(&mut start)?;
+
+    if &start == b"PROXY" {
+        read_v1(stream)
+    } else if start == SIGNATURE[..5] {
+        read_v2(stream)
+    } else {
+        Err(Invalid::Proxy.into())
+    }
+}
+
+/// Read the remainder of a human-readable header, e.g. `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`.
+fn read_v1(mut stream: impl Read) -> Result<Option<SocketAddr>> {
+    // read byte by byte to leave the request itself untouched
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= MAX_LINE {
+            return Err(Invalid::Proxy.into());
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+
+    let line = String::from_utf8_lossy(&line);
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [proto @ ("TCP4" | "TCP6"), src, _, src_port, _] => {
+            // the address has to be of the family the protocol names
+            let addr = match *proto {
+                "TCP4" => Ipv4Addr::from_str(src).map(IpAddr::V4),
+                _ => Ipv6Addr::from_str(src).map(IpAddr::V6),
+            }
+            .map_err(|_| Invalid::Proxy)?;
+            let port = src_port.parse().map_err(|_| Invalid::Proxy)?;
+            Ok(Some(SocketAddr::new(addr, port)))
+        }
+        ["UNKNOWN", ..] => Ok(None),
+        _ => Err(Invalid::Proxy.into()),
+    }
+}
+
+/// Read the remainder of a binary header.
+fn read_v2(mut stream: impl Read) -> Result<Option<SocketAddr>> {
+    // the rest of the signature, the version and command, the family and the length of the addresses
+    let mut head = [0; 11];
+    stream.read_exact(&mut head)?;
+    if head[..7] != SIGNATURE[5..] || head[7] >> 4 != 2 {
+        return Err(Invalid::Proxy.into());
+    }
+
+    let mut body = vec![0; usize::from(u16::from_be_bytes([head[9], head[10]]))];
+    stream.read_exact(&mut body)?;
+
+    // connections of the load balancer itself, such as health checks
+    if head[7] & 0xF == 0 {
+        return Ok(None);
+    }
+
+    // only the source address and port are of interest, anything after them is ignored
+    match (head[8] >> 4, body.as_slice()) {
+        (1, [a, b, c, d, _, _, _, _, p0, p1, _, _, ..]) => Ok(Some(SocketAddr::new(
+            Ipv4Addr::new(*a, *b, *c, *d).into(),
+            u16::from_be_bytes([*p0, *p1]),
+        ))),
+        (2, body) if body.len() >= 36 => {
+            let mut addr = [0; 16];
+            addr.copy_from_slice(&body[..16]);
+            Ok(Some(SocketAddr::new(
+                Ipv6Addr::from(addr).into(),
+                u16::from_be_bytes([body[32], body[33]]),
+            )))
+        }
+        (0 | 3, _) => Ok(None),
+        _ => Err(Invalid::Proxy.into()),
+    }
+}
+
+/// Determinant for whether the peer is a proxy whose word on the address of the client is believed,
+/// which local proxies on a Unix domain socket always are.
+pub fn is_trusted(peer: SocketAddr, trusted: &[Cidr]) -> bool {
+    peer == UNIX_PEER || trusted.iter().any(|cidr| cidr.contains(peer.ip()))
+}
+
+/// Address of the client according to the PROXY protocol header the peer begins with, which is always read,
+/// but only believed if the peer is a trusted proxy.
+pub fn proxied_addr(stream: impl Read, peer: SocketAddr, trusted: &[Cidr]) -> Result<SocketAddr> {
+    match read_proxy_header(stream)? {
+        Some(addr) if is_trusted(peer, trusted) => Ok(addr),
+        Some(addr) => {
+            debug!(
+                "Ignored the PROXY protocol header of {}, claiming {}: not a trusted proxy",
+                peer, addr
+            );
+            Ok(peer)
+        }
+        None => Ok(peer),
+    }
+}
+
+/// Every hop of the request, from the client onwards, according to the `Forwarded`, `X-Forwarded-For` or `X-Real-IP`
+/// headers, in that order of preference.
+fn hops(headers: &[Header]) -> Result<Vec<String>, UriError> {
+    let values = |name: &str| -> Result<Vec<String>, UriError> {
+        headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| Ok(String::from_utf8(h.value.to_vec())?))
+            .collect()
+    };
+
+    let forwarded = values("Forwarded")?;
+    if !forwarded.is_empty() {
+        // each element may have a `for` parameter, e.g. `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+        return Ok(forwarded
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .map_or("unknown", |(_, value)| value.trim().trim_matches('"'))
+                    .to_owned()
+            })
+            .collect());
+    }
+
+    let forwarded_for = values("X-Forwarded-For")?;
+    if !forwarded_for.is_empty() {
+        return Ok(forwarded_for
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|hop| hop.trim().to_owned())
+            .collect());
+    }
+
+    values("X-Real-IP")
+}
+
+/// Parse the address of a hop, which may include a port, returning `None` for unknown or obfuscated identifiers.
+fn parse_hop(hop: &str) -> Result<Option<IpAddr>, UriError> {
+    if hop.eq_ignore_ascii_case("unknown") || hop.starts_with('_') {
+        return Ok(None);
+    }
+
+    let addr = if let Some(bracketed) = hop.strip_prefix('[') {
+        // `[2001:db8::1]:4711`
+        bracketed.split_once(']').map_or("", |(addr, _)| addr)
+    } else if hop.matches(':').count() == 1 {
+        // `192.0.2.60:4711`
+        hop.split(':').next().unwrap_or_default()
+    } else {
+        hop
+    };
+    Ok(Some(IpAddr::from_str(addr)?))
+}
+
+/// Address of the client behind any trusted proxies, where the forwarding headers are only believed
+/// for as long as each hop along the way, starting with the peer itself, is trusted.
+/// The peers of a Unix domain socket are local proxies, and so are always trusted.
+pub fn client_addr(
+    headers: &[Header],
+    peer: SocketAddr,
+    trusted: &[Cidr],
+) -> Result<SocketAddr, UriError> {
+    let mut addr = peer.ip();
+    for (i, hop) in hops(headers)?.iter().rev().enumerate() {
+        let is_local = i == 0 && peer == UNIX_PEER;
+        if !is_local && !trusted.iter().any(|cidr| cidr.contains(addr)) {
+            break;
+        }
+        match parse_hop(hop)? {
+            Some(hop) => addr = hop,
+            None => break,
+        }
+    }
+    Ok(SocketAddr::new(addr, peer.port()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 40000);
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+    /// A binary header of the given command, family and addresses.
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(body.len()).unwrap().to_be_bytes();
+        let mut header = SIGNATURE.to_vec();
+        header.extend([0x20 | command, family << 4 | 1, len[0], len[1]]);
+        header.extend(body);
+        header
+    }
+
+    /// Addresses and ports of an IPv4 connection from `192.0.2.1:56324` to `192.0.2.2:443`.
+    const TCP4: [u8; 12] = [192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x01, 0xBB];
+
+    fn read(header: &[u8]) -> Result<Option<SocketAddr>> {
+        read_proxy_header(header)
+    }
+
+    fn trusted(cidrs: &[&str]) -> Vec<Cidr> {
+        cidrs.iter().map(|c| c.parse().unwrap()).collect()
+    }
+
+    fn client(headers: &[(&str, &str)], peer: SocketAddr, cidrs: &[&str]) -> Option<IpAddr> {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| Header {
+                name,
+                value: value.as_bytes(),
+            })
+            .collect::<Vec<_>>();
+        client_addr(&headers, peer, &trusted(cidrs))
+            .ok()
+            .map(|addr| addr.ip())
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn v1_addresses() {
+        let addr = read(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n").unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+
+        let addr = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert_eq!(
+            read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn v1_leaves_the_request_untouched() {
+        let stream = [b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n", REQUEST].concat();
+        let mut rest = stream.as_slice();
+        assert!(read_proxy_header(&mut rest).is_ok());
+        assert_eq!(rest, REQUEST);
+    }
+
+    #[test]
+    fn v1_malformed() {
+        for header in [
+            &b"PROXY TCP4 192.0.2.256 192.0.2.2 56324 443\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n".as_slice(),
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n",
+            b"PROXY TCP4 192.0.2.1:56324 192.0.2.2 56324 443\r\n",
+            b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\n",
+            b"PROXY\r\n",
+            b"PROX",
+            b"GET / HTTP/1.1\r\n\r\n",
+        ] {
+            assert!(
+                read(header).is_err(),
+                "{:?}",
+                String::from_utf8_lossy(header)
+            )
+        }
+
+        // a line that never ends is cut off at the longest one allowed
+        let endless = [b"PROXY TCP6 ".as_slice(), &[b'f'; 1024]].concat();
+        assert!(read(&endless).is_err());
+    }
+
+    #[test]
+    fn v2_addresses() {
+        let addr = read(&v2(1, 1, &TCP4)).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+
+        let mut body = [0; 36];
+        body[..16].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body[32..34].copy_from_slice(&56324u16.to_be_bytes());
+        let addr = read(&v2(1, 2, &body)).unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        // any TLVs after the addresses are skipped along with them
+        let stream = [
+            v2(1, 1, &[&TCP4[..], &[0x04, 0, 1, 0]].concat()),
+            REQUEST.to_vec(),
+        ]
+        .concat();
+        let mut rest = stream.as_slice();
+        assert!(read_proxy_header(&mut rest).unwrap().is_some());
+        assert_eq!(rest, REQUEST);
+    }
+
+    #[test]
+    fn v2_local_and_unspecified() {
+        // health checks of the load balancer itself, regardless of any addresses they carry
+        assert_eq!(read(&v2(0, 1, &TCP4)).unwrap(), None);
+        assert_eq!(read(&v2(0, 0, &[])).unwrap(), None);
+
+        // unspecified and Unix domain socket addresses
+        assert_eq!(read(&v2(1, 0, &[])).unwrap(), None);
+        assert_eq!(read(&v2(1, 3, &[0; 216])).unwrap(), None);
+    }
+
+    #[test]
+    fn v2_unknown_family_or_command() {
+        assert!(read(&v2(1, 4, &TCP4)).is_err());
+        assert!(read(&v2(1, 0xF, &TCP4)).is_err());
+
+        // an unknown command is only ever a proxied connection, whose addresses are believed
+        assert!(read(&v2(0xF, 1, &TCP4)).unwrap().is_some());
+
+        // versions other than 2
+        let mut header = v2(1, 1, &TCP4);
+        header[12] = 0x11;
+        assert!(read(&header).is_err());
+        header[12] = 0x31;
+        assert!(read(&header).is_err());
+    }
+
+    #[test]
+    fn v2_truncated() {
+        let header = v2(1, 1, &TCP4);
+        for len in 0..header.len() {
+            assert!(read(&header[..len]).is_err(), "{}", len)
+        }
+
+        // the stated length is too short for the family
+        assert!(read(&v2(1, 1, &TCP4[..11])).is_err());
+        assert!(read(&v2(1, 2, &[0; 35])).is_err());
+
+        // the signature is off
+        let mut header = v2(1, 1, &TCP4);
+        header[6] = b'X';
+        assert!(read(&header).is_err());
+    }
+
+    #[test]
+    fn only_trusted_peers_are_believed() {
+        let header = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n";
+        let client = "192.0.2.1:56324".parse().unwrap();
+
+        let addr = proxied_addr(&header[..], PEER, &trusted(&["10.0.0.0/8"])).unwrap();
+        assert_eq!(addr, client);
+        let addr = proxied_addr(&header[..], UNIX_PEER, &[]).unwrap();
+        assert_eq!(addr, client);
+
+        // the header is still read off the stream, but its address ignored
+        let stream = [header.as_slice(), REQUEST].concat();
+        let mut rest = stream.as_slice();
+        let addr = proxied_addr(&mut rest, PEER, &trusted(&["10.0.0.2"])).unwrap();
+        assert_eq!(addr, PEER);
+        assert_eq!(rest, REQUEST);
+
+        // health checks are the peer itself
+        let addr = proxied_addr(&v2(0, 0, &[])[..], PEER, &trusted(&["10.0.0.1"])).unwrap();
+        assert_eq!(addr, PEER);
+    }
+
+    #[test]
+    fn hops_of_every_header() {
+        assert_eq!(parse_hop("192.0.2.60").ok(), Some(ip("192.0.2.60")));
+        assert_eq!(parse_hop("192.0.2.60:4711").ok(), Some(ip("192.0.2.60")));
+        assert_eq!(parse_hop("2001:db8::1").ok(), Some(ip("2001:db8::1")));
+        assert_eq!(
+            parse_hop("[2001:db8::1]:4711").ok(),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_hop("[2001:db8::1]").ok(), Some(ip("2001:db8::1")));
+        assert_eq!(parse_hop("unknown").ok(), Some(None));
+        assert_eq!(parse_hop("_hidden").ok(), Some(None));
+        for hop in [
+            "",
+            "example.com",
+            "192.0.2.60:4711:1",
+            "[2001:db8::1",
+            "300.0.0.1",
+        ] {
+            assert!(parse_hop(hop).is_err(), "{:?}", hop)
+        }
+    }
+
+    #[test]
+    fn forwarded_for_chains() {
+        let trusted = ["10.0.0.0/8"];
+        let chain = [("X-Forwarded-For", "203.0.113.5, 198.51.100.7, 10.0.0.2")];
+
+        // the chain is believed back to the first hop that isn't trusted, past which anything may be spoofed
+        assert_eq!(client(&chain, PEER, &trusted), ip("198.51.100.7"));
+
+        // an untrusted peer is the client, whatever it claims
+        assert_eq!(client(&chain, PEER, &["10.0.0.2"]), ip("10.0.0.1"));
+        assert_eq!(client(&chain, PEER, &[]), ip("10.0.0.1"));
+
+        // every hop is trusted, so the first one is the client
+        assert_eq!(client(&chain, PEER, &["0.0.0.0/0"]), ip("203.0.113.5"));
+
+        // the chain may be spread over several headers
+        let headers = [
+            ("X-Forwarded-For", "203.0.113.5"),
+            ("x-forwarded-for", "10.0.0.3, 10.0.0.2"),
+        ];
+        assert_eq!(client(&headers, PEER, &trusted), ip("203.0.113.5"));
+
+        // an unknown hop ends the walk at the last one known
+        let unknown = [("X-Forwarded-For", "203.0.113.5, unknown, 10.0.0.2")];
+        assert_eq!(client(&unknown, PEER, &trusted), ip("10.0.0.2"));
+
+        // a malformed hop is only ever noticed when it is reached
+        let malformed = [("X-Forwarded-For", "bogus, 198.51.100.7, 10.0.0.2")];
+        assert_eq!(client(&malformed, PEER, &trusted), ip("198.51.100.7"));
+        assert_eq!(client(&malformed, PEER, &["0.0.0.0/0"]), None);
+    }
+
+    #[test]
+    fn forwarded_is_preferred() {
+        let headers = [
+            ("X-Real-IP", "192.0.2.3"),
+            ("X-Forwarded-For", "192.0.2.2"),
+            (
+                "Forwarded",
+                "for=\"[2001:db8::1]:4711\";proto=https, for=192.0.2.60;by=10.0.0.2",
+            ),
+        ];
+        assert_eq!(client(&headers, PEER, &["10.0.0.0/8"]), ip("192.0.2.60"));
+        assert_eq!(
+            client(&headers, PEER, &["10.0.0.0/8", "192.0.2.60"]),
+            ip("2001:db8::1")
+        );
+
+        // an element without `for` is an unknown hop
+        let headers = [("Forwarded", "for=192.0.2.60, proto=http")];
+        assert_eq!(client(&headers, PEER, &["10.0.0.0/8"]), ip("10.0.0.1"));
+
+        assert_eq!(
+            client(&[("X-Real-IP", "192.0.2.3")], PEER, &["10.0.0.0/8"]),
+            ip("192.0.2.3")
+        );
+    }
+
+    #[test]
+    fn unix_peers_are_local_proxies() {
+        let chain = [("X-Forwarded-For", "203.0.113.5, 198.51.100.7")];
+
+        // only the hop the local proxy itself adds is believed without being trusted
+        assert_eq!(client(&chain, UNIX_PEER, &[]), ip("198.51.100.7"));
+        assert_eq!(
+            client(&chain, UNIX_PEER, &["198.51.100.7"]),
+            ip("203.0.113.5")
+        );
+        assert_eq!(client(&[], UNIX_PEER, &[]), ip("0.0.0.0"));
+    }
+}
@@ -0,0 +1,156 @@
+use log::warn;
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use super::AsciiFrame;
+
+/// How far the schedule may fall behind before it is reset instead of caught up
+const MAX_DRIFT: Duration = Duration::from_secs(1);
+
+/// Paces the distribution of frames against absolute deadlines
+#[derive(Default)]
+pub struct Scheduler {
+    index: usize,
+    deadline: Option<Instant>,
+    skipped: usize,
+    max_drift: Duration,
+}
+
+impl Scheduler {
+    /// Index of the frame that is due.
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Forget the current deadline so playback resumes immediately.
+    pub fn pause(&mut self) {
+        self.deadline = None
+    }
+
+    /// Move onto the next frame, returning `true` if the animation wrapped around.
+    fn step(&mut self, count: usize) -> bool {
+        self.index = (self.index + 1) % count;
+        self.index == 0
+    }
+
+    /// Sleep until the next frame is due, skipping every frame that is already overdue.
+    /// Returns `true` once the animation has wrapped around.
+    pub fn advance(&mut self, frames: &[AsciiFrame]) -> bool {
+        let wrapped = self.schedule(frames, Instant::now());
+        if let Some(deadline) = self.deadline {
+            sleep(deadline.saturating_duration_since(Instant::now()))
+        }
+        wrapped
+    }
+
+    /// Set the deadline of the next frame as of the given time, skipping every frame that is already overdue.
+    fn schedule(&mut self, frames: &[AsciiFrame], now: Instant) -> bool {
+        // the deadline of the next frame, based on when the current one was due
+        let mut deadline = self.deadline.unwrap_or(now) + frames[self.index].delay();
+        let mut wrapped = self.step(frames.len());
+
+        // the distance the schedule has fallen behind
+        let drift = now.saturating_duration_since(deadline);
+        self.max_drift = self.max_drift.max(drift);
+
+        if drift > MAX_DRIFT {
+            warn!("Behind schedule by {:?}, resynchronizing", drift);
+            deadline = now;
+        } else {
+            // drop every frame that would have already been replaced
+            while now >= deadline + frames[self.index].delay() {
+                deadline += frames[self.index].delay();
+                wrapped |= self.step(frames.len());
+                self.skipped += 1;
+            }
+        }
+        self.deadline = Some(deadline);
+
+        if wrapped {
+            self.report()
+        }
+        wrapped
+    }
+
+    /// Emit the drift of the last cycle, if any frames had to be skipped.
+    fn report(&mut self) {
+        if self.skipped > 0 {
+            warn!(
+                "Skipped {} frames to keep up (max drift {:?})",
+                self.skipped, self.max_drift
+            )
+        }
+        self.skipped = 0;
+        self.max_drift = Duration::ZERO;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(100);
+
+    fn frames() -> Vec<AsciiFrame> {
+        vec![AsciiFrame::new(Vec::new(), DELAY); 4]
+    }
+
+    #[test]
+    fn deadlines() {
+        let (frames, start) = (frames(), Instant::now());
+        let mut scheduler = Scheduler::default();
+
+        // every deadline follows the last one, however late the frame goes out
+        for (ms, index) in [(0, 1), (100, 2), (250, 3)] {
+            assert!(!scheduler.schedule(&frames, start + Duration::from_millis(ms)));
+            assert_eq!(scheduler.index(), index);
+            assert_eq!(scheduler.deadline, Some(start + DELAY * index as u32));
+        }
+        assert!(scheduler.schedule(&frames, start + Duration::from_millis(300)));
+        assert_eq!(scheduler.index(), 0);
+        assert_eq!(scheduler.skipped, 0);
+    }
+
+    #[test]
+    fn catch_up() {
+        let (frames, start) = (frames(), Instant::now());
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(&frames, start);
+
+        // frames that would have already been replaced are skipped
+        assert!(!scheduler.schedule(&frames, start + Duration::from_millis(350)));
+        assert_eq!(scheduler.index(), 3);
+        assert_eq!(scheduler.deadline, Some(start + DELAY * 3));
+        assert_eq!(scheduler.skipped, 1);
+        assert_eq!(scheduler.max_drift, Duration::from_millis(150));
+
+        // and reported once the animation wraps around, which starts the count over
+        assert!(scheduler.schedule(&frames, start + Duration::from_millis(360)));
+        assert_eq!(scheduler.skipped, 0);
+        assert_eq!(scheduler.max_drift, Duration::ZERO);
+    }
+
+    #[test]
+    fn resynchronize() {
+        let (frames, start) = (frames(), Instant::now());
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(&frames, start);
+
+        // falling too far behind starts the schedule over from now
+        let now = start + MAX_DRIFT * 2;
+        scheduler.schedule(&frames, now);
+        assert_eq!(scheduler.index(), 2);
+        assert_eq!(scheduler.deadline, Some(now));
+        assert_eq!(scheduler.skipped, 0);
+
+        // as does a pause, which adds no drift of its own
+        scheduler.pause();
+        let now = now + MAX_DRIFT * 2;
+        scheduler.schedule(&frames, now);
+        assert_eq!(scheduler.index(), 3);
+        assert_eq!(scheduler.deadline, Some(now + DELAY));
+        assert_eq!(scheduler.max_drift, MAX_DRIFT * 2 - DELAY * 2);
+    }
+}
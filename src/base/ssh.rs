@@ -0,0 +1,670 @@
+use log::{debug, info};
+use parking_lot::Mutex;
+use russh::{
+    keys::{load_secret_key, ssh_key::LineEnding, Algorithm, HashAlg, PrivateKey},
+    server::{self, Auth, ChannelOpenHandle, Msg, Session as Connection},
+    Channel, ChannelId, ChannelWriteHalf, MethodKind, MethodSet,
+};
+use std::{
+    fs::OpenOptions,
+    io::{Error, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    runtime::{Builder, Handle, Runtime},
+    sync::{oneshot, watch},
+    time::{timeout, timeout_at},
+};
+
+use super::{raw_viewer, Config, Invalid, Mount, Response, Result, Stream, Viewer, Window};
+
+/// How often a client that sends nothing is asked whether it's still there
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// How long a client has to close its session once the stream has ended
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn clamp(n: u32) -> u16 {
+    n.try_into().unwrap_or(u16::MAX)
+}
+
+/// The long-term key that identifies the server, which is generated on first run,
+/// along with the runtime every session is driven on
+pub struct HostKey {
+    config: Arc<server::Config>,
+    runtime: Runtime,
+}
+
+impl HostKey {
+    /// Read the OpenSSH file of the key, generating it first if it doesn't exist yet.
+    pub fn new(path: &Path) -> Result<Self> {
+        let key = match load_secret_key(path, None) {
+            Ok(key) => key,
+            Err(russh::keys::Error::IO(e)) if e.kind() == ErrorKind::NotFound => {
+                let key = PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519)
+                    .map_err(russh::keys::Error::from)?;
+
+                // only the owner may ever read the private key
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let pem = key
+                    .to_openssh(LineEnding::LF)
+                    .map_err(russh::keys::Error::from)?;
+                options.open(path)?.write_all(pem.as_bytes())?;
+
+                info!("Generated the SSH host key {}", path.display());
+                key
+            }
+            Err(e) => return Err(e.into()),
+        };
+        info!(
+            "SSH host key {}: {}",
+            path.display(),
+            key.fingerprint(HashAlg::Sha256)
+        );
+        Self::from_key(key)
+    }
+
+    fn from_key(key: PrivateKey) -> Result<Self> {
+        // clients authenticate with nothing but their username
+        let config = server::Config {
+            methods: MethodSet::from(&[MethodKind::None][..]),
+            keys: vec![key],
+            inactivity_timeout: None,
+            keepalive_interval: Some(KEEPALIVE),
+            nodelay: true,
+            ..Default::default()
+        };
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("ssh")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            config: Arc::new(config),
+            runtime,
+        })
+    }
+
+    /// Begin the server side of a connection, where the handshake happens along with the viewer.
+    pub fn accept(&self, socket: TcpStream) -> std::io::Result<SshStream> {
+        Ok(SshStream {
+            socket: socket.try_clone()?,
+            session: Arc::new(Session {
+                config: self.config.clone(),
+                runtime: self.runtime.handle().clone(),
+                socket,
+                window: Arc::default(),
+                channel: Mutex::default(),
+                closed: watch::Sender::new(false),
+            }),
+        })
+    }
+}
+
+/// The sending half of the session channel
+struct Sender {
+    half: ChannelWriteHalf<Msg>,
+    writer: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+/// What the client asked for once its session channel was open
+type Opened = (Option<String>, ChannelWriteHalf<Msg>);
+
+/// The callbacks of a session, which only ever allow a single session channel with a terminal
+struct Handler {
+    users: Vec<String>,
+    window: Arc<Window>,
+    channel: Option<Channel<Msg>>,
+    opened: Option<oneshot::Sender<Opened>>,
+}
+
+impl Handler {
+    /// Begin the stream on the session channel, handing it over along with the command, if any.
+    fn open(
+        &mut self,
+        id: ChannelId,
+        query: Option<String>,
+        session: &mut Connection,
+    ) -> Result<(), russh::Error> {
+        match (self.channel.take(), self.opened.take()) {
+            (Some(channel), Some(opened)) if channel.id() == id => {
+                session.channel_success(id)?;
+                // whatever the client types comes through `data`, so nothing ever waits on the read half
+                let (_, half) = channel.split();
+                _ = opened.send((query, half));
+                Ok(())
+            }
+            (channel, opened) => {
+                self.channel = channel;
+                self.opened = opened;
+                session.channel_failure(id)
+            }
+        }
+    }
+}
+
+impl server::Handler for Handler {
+    type Error = russh::Error;
+
+    /// Any username is accepted, unless some are configured, without any credentials.
+    async fn auth_none(&mut self, user: &str) -> Result<Auth, Self::Error> {
+        Ok(
+            match self.users.is_empty() || self.users.iter().any(|u| u == user) {
+                true => Auth::Accept,
+                false => Auth::reject(),
+            },
+        )
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _: &mut Connection,
+    ) -> Result<(), Self::Error> {
+        // dropping the reply refuses every channel but the first
+        if self.channel.is_none() && self.opened.is_some() {
+            reply.accept().await;
+            self.channel = Some(channel);
+        }
+        Ok(())
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _: &str,
+        cols: u32,
+        rows: u32,
+        _: u32,
+        _: u32,
+        _: &[(russh::Pty, u32)],
+        session: &mut Connection,
+    ) -> Result<(), Self::Error> {
+        self.window.resize(clamp(cols), clamp(rows));
+        session.channel_success(channel)
+    }
+
+    async fn window_change_request(
+        &mut self,
+        _: ChannelId,
+        cols: u32,
+        rows: u32,
+        _: u32,
+        _: u32,
+        _: &mut Connection,
+    ) -> Result<(), Self::Error> {
+        self.window.resize(clamp(cols), clamp(rows));
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Connection,
+    ) -> Result<(), Self::Error> {
+        self.open(channel, None, session)
+    }
+
+    /// A command is taken as the query of the stream.
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Connection,
+    ) -> Result<(), Self::Error> {
+        let query = String::from_utf8_lossy(data).into_owned();
+        self.open(channel, Some(query), session)
+    }
+
+    /// What the client types is never taken as anything but keys, its terminal is only ever resized by
+    /// `window-change` requests.
+    async fn data(
+        &mut self,
+        _: ChannelId,
+        data: &[u8],
+        _: &mut Connection,
+    ) -> Result<(), Self::Error> {
+        self.window.hang_up(data);
+        Ok(())
+    }
+}
+
+/// The server side of a connection, driven on the runtime of its host key
+struct Session {
+    config: Arc<server::Config>,
+    runtime: Handle,
+    socket: TcpStream,
+    window: Arc<Window>,
+    channel: Mutex<Option<Sender>>,
+    /// Set once the session has ended, which wakes any writer waiting for the client to take more
+    closed: watch::Sender<bool>,
+}
+
+impl Session {
+    /// Authenticate the client, then wait for it to open a session and ask for a shell by the deadline.
+    fn establish(self: &Arc<Self>, users: &[String], deadline: Instant) -> Result<Option<String>> {
+        let (opened, shell) = oneshot::channel();
+        let handler = Handler {
+            users: users.to_vec(),
+            window: self.window.clone(),
+            channel: None,
+            opened: Some(opened),
+        };
+        let socket = self.socket.try_clone()?;
+        socket.set_nonblocking(true)?;
+
+        let session = self.clone();
+        let res = self.runtime.block_on(async move {
+            let socket = tokio::net::TcpStream::from_std(socket)?;
+            let running = timeout_at(
+                deadline.into(),
+                server::run_stream(session.config.clone(), socket, handler),
+            )
+            .await
+            .map_err(|_| Error::from(ErrorKind::TimedOut))??;
+
+            // let any writer know once the session ends, however it does
+            let closed = session.clone();
+            tokio::spawn(async move {
+                if let Err(e) = running.await {
+                    debug!("Ended the SSH session of {}: {}", closed.peer(), e)
+                }
+                closed.closed.send_replace(true);
+            });
+
+            match timeout_at(deadline.into(), shell).await {
+                Ok(Ok(opened)) => Ok(opened),
+                Ok(Err(_)) => Err(Error::from(ErrorKind::UnexpectedEof).into()),
+                Err(_) => Err(Error::from(ErrorKind::TimedOut).into()),
+            }
+        });
+
+        // the session would otherwise go on without anyone to end it
+        let (query, half) = match res {
+            Ok(opened) => opened,
+            Err(e) => {
+                _ = self.socket.shutdown(Shutdown::Both);
+                return Err(e);
+            }
+        };
+        debug!("Opened an SSH session for {}", self.peer());
+        let writer = Box::pin(half.make_writer());
+        *self.channel.lock() = Some(Sender { half, writer });
+        Ok(query)
+    }
+
+    fn peer(&self) -> String {
+        self.socket
+            .peer_addr()
+            .map_or_else(|_| "?".to_owned(), |a| a.to_string())
+    }
+
+    /// End the session, as if the shell exited, then wait for the client to close it.
+    fn close(&self, sender: &Sender) {
+        let mut closed = self.closed.subscribe();
+        self.runtime.block_on(async {
+            let half = &sender.half;
+            _ = half.exit_status(0).await;
+            _ = half.eof().await;
+            _ = half.close().await;
+            _ = timeout(CLOSE_TIMEOUT, closed.wait_for(|c| *c)).await;
+        })
+    }
+}
+
+/// An SSH connection, which can be shared between the threads writing and disconnecting it
+pub struct SshStream {
+    session: Arc<Session>,
+    socket: TcpStream,
+}
+
+impl SshStream {
+    /// The underlying TCP socket.
+    pub const fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            session: self.session.clone(),
+            socket: self.socket.try_clone()?,
+        })
+    }
+
+    /// End the session, unless another thread is still writing, then shut the socket down.
+    /// Shutting down both halves ends the session right away, which any writer gives up on.
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        match how {
+            Shutdown::Both => {
+                self.session.closed.send_replace(true);
+            }
+            Shutdown::Write => {
+                if let Some(channel) = self.session.channel.try_lock() {
+                    if let Some(sender) = channel.as_ref() {
+                        self.session.close(sender)
+                    }
+                }
+            }
+            Shutdown::Read => {}
+        }
+        match self.socket.shutdown(how) {
+            Err(e) if e.kind() == ErrorKind::NotConnected => Ok(()),
+            res => res,
+        }
+    }
+}
+
+/// Whatever the client types is handled by the session itself.
+impl Read for &SshStream {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+/// Data sent over the session channel, for as much as the client is willing to receive,
+/// within the write timeout of the socket.
+impl Write for &SshStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let session = &self.session;
+        let mut channel = session.channel.lock();
+        let Some(sender) = channel.as_mut() else {
+            return Err(ErrorKind::NotConnected.into());
+        };
+        let mut closed = session.closed.subscribe();
+        let limit = self.socket.write_timeout()?;
+
+        session.runtime.block_on(async {
+            let write = async {
+                match limit {
+                    Some(limit) => timeout(limit, sender.writer.write(buf))
+                        .await
+                        .unwrap_or_else(|_| Err(ErrorKind::TimedOut.into())),
+                    None => sender.writer.write(buf).await,
+                }
+            };
+            tokio::select! {
+                biased;
+                _ = closed.wait_for(|c| *c) => Err(ErrorKind::BrokenPipe.into()),
+                res = write => res,
+            }
+        })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stream the animation of the raw port to an SSH client, fitted to the size of its terminal,
+/// once it has asked for a shell by the deadline.
+pub fn ssh_viewer(
+    stream: &Stream,
+    peer: SocketAddr,
+    mounts: &[Mount],
+    cfg: &Config,
+    deadline: Instant,
+) -> Result<Viewer> {
+    let Stream::Ssh(ssh) = stream else {
+        return Err(Invalid::Format.into());
+    };
+    let query = ssh.session.establish(cfg.ssh_users(), deadline)?;
+
+    let viewer = raw_viewer(peer, mounts, cfg)?;
+    let options = viewer.options.clone();
+    Ok(Viewer {
+        options: options.with_query(query.as_deref().unwrap_or_default())?,
+        response: Response::Ssh,
+        window: Some(ssh.session.window.clone()),
+        ..viewer
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use russh::{
+        client,
+        keys::{PublicKey, PublicKeyOrCertificate},
+        ChannelMsg, Disconnect,
+    };
+    use std::{
+        net::TcpListener,
+        thread::{sleep, spawn, JoinHandle},
+    };
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    /// A client that trusts the one host key it's given
+    struct Trusting(PublicKey);
+
+    impl client::Handler for Trusting {
+        type Error = russh::Error;
+
+        async fn check_server_key(
+            &mut self,
+            key: &PublicKeyOrCertificate,
+        ) -> Result<bool, Self::Error> {
+            Ok(matches!(key, PublicKeyOrCertificate::PublicKey { key, .. } if *key == self.0))
+        }
+    }
+
+    /// A session of a new host key over loopback, which is being established on a thread of its own,
+    /// along with the socket of its client
+    struct Setup {
+        host_key: HostKey,
+        ssh: SshStream,
+        establishing: JoinHandle<Result<Option<String>>>,
+        socket: TcpStream,
+    }
+
+    impl Setup {
+        /// Connect a client, which only the given users may authenticate as.
+        fn new(users: &[&str]) -> Self {
+            let key = PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519).unwrap();
+            let host_key = HostKey::from_key(key).unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (accepted, _) = listener.accept().unwrap();
+            let ssh = host_key.accept(accepted).unwrap();
+
+            let session = ssh.session.clone();
+            let users = users.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+            let establishing =
+                spawn(move || session.establish(&users, Instant::now() + Duration::from_secs(5)));
+            Self {
+                host_key,
+                ssh,
+                establishing,
+                socket,
+            }
+        }
+
+        /// Begin the client side of the session on the given runtime.
+        fn client(&self, runtime: &Runtime, window_size: u32) -> client::Handle<Trusting> {
+            let socket = self.socket.try_clone().unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let config = Arc::new(client::Config {
+                window_size,
+                ..Default::default()
+            });
+            let public = self.host_key.config.keys[0].public_key().clone();
+            let _guard = runtime.enter();
+            let socket = tokio::net::TcpStream::from_std(socket).unwrap();
+            runtime
+                .block_on(client::connect_stream(config, socket, Trusting(public)))
+                .unwrap()
+        }
+    }
+
+    /// A runtime for clients that keep running on their own.
+    fn runtime() -> Runtime {
+        Runtime::new().unwrap()
+    }
+
+    /// Wait for the condition to hold, for up to 5 seconds.
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() && Instant::now() < deadline {
+            sleep(Duration::from_millis(10))
+        }
+        condition()
+    }
+
+    #[test]
+    fn session() {
+        let setup = Setup::new(&[]);
+        let runtime = runtime();
+        let mut handle = setup.client(&runtime, 1 << 20);
+
+        let mut channel = runtime.block_on(async {
+            assert!(handle.authenticate_none("viewer").await.unwrap().success());
+            let channel = handle.channel_open_session().await.unwrap();
+            channel
+                .request_pty(true, "xterm", 100, 30, 0, 0, &[])
+                .await
+                .unwrap();
+            channel.exec(true, "speed=2").await.unwrap();
+            channel
+        });
+        assert_eq!(
+            setup.establishing.join().unwrap().unwrap().as_deref(),
+            Some("speed=2")
+        );
+        let window = setup.ssh.session.window.clone();
+        assert_eq!(window.size(), (100, 30));
+
+        // the session is sent whatever is written to the stream
+        (&setup.ssh).write_all(b"donut").unwrap();
+        runtime.block_on(async {
+            loop {
+                match channel.wait().await.unwrap() {
+                    ChannelMsg::Data { data } => break assert_eq!(&data[..], b"donut"),
+                    _ => continue,
+                }
+            }
+        });
+
+        // the terminal is only ever resized by requests, never by what the client types
+        runtime.block_on(async {
+            channel.window_change(120, 40, 0, 0).await.unwrap();
+            channel
+                .data(&[0xFF, 0xFA, 31, 0, 1, 0, 1, 0xFF, 0xF0][..])
+                .await
+                .unwrap();
+        });
+        assert!(eventually(|| window.size() == (120, 40)));
+        assert!(!window.is_hung_up());
+        runtime.block_on(async { channel.data(&b"q"[..]).await.unwrap() });
+        assert!(eventually(|| window.is_hung_up()));
+        assert_eq!(window.size(), (120, 40));
+
+        // the session ends as if the shell exited
+        let ssh = setup.ssh;
+        let ending = spawn(move || ssh.shutdown(Shutdown::Write));
+        let messages = runtime.block_on(async {
+            let mut messages = Vec::new();
+            while let Some(msg) = channel.wait().await {
+                messages.push(msg)
+            }
+            messages
+        });
+        assert!(messages
+            .iter()
+            .any(|m| matches!(m, ChannelMsg::ExitStatus { exit_status: 0 })));
+        assert!(messages.iter().any(|m| matches!(m, ChannelMsg::Eof)));
+        runtime.block_on(async {
+            _ = handle.disconnect(Disconnect::ByApplication, "", "").await;
+        });
+        ending.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn shell() {
+        let setup = Setup::new(&["donut"]);
+        let runtime = runtime();
+        let mut handle = setup.client(&runtime, 1 << 20);
+        let _channel = runtime.block_on(async {
+            assert!(handle.authenticate_none("donut").await.unwrap().success());
+            let channel = handle.channel_open_session().await.unwrap();
+
+            // only one session is ever open
+            assert!(handle.channel_open_session().await.is_err());
+            channel.request_shell(true).await.unwrap();
+            channel
+        });
+        assert_eq!(setup.establishing.join().unwrap().unwrap(), None);
+    }
+
+    #[test]
+    fn unknown_user() {
+        let setup = Setup::new(&["donut"]);
+        let runtime = runtime();
+        let mut handle = setup.client(&runtime, 1 << 20);
+        runtime.block_on(async {
+            assert!(!handle.authenticate_none("viewer").await.unwrap().success());
+            _ = handle.disconnect(Disconnect::ByApplication, "", "").await;
+        });
+        drop(handle);
+        assert!(setup.establishing.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn silent_client() {
+        let key = PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519).unwrap();
+        let host_key = HostKey::from_key(key).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let ssh = host_key.accept(listener.accept().unwrap().0).unwrap();
+
+        // a client that never says anything is given up on by the deadline
+        let start = Instant::now();
+        let err = ssh
+            .session
+            .establish(&[], start + Duration::from_millis(200))
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(matches!(err, super::super::Error::IO(e) if e.kind() == ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn hung_up_writer() {
+        let setup = Setup::new(&[]);
+        // the client only runs while the test waits on its runtime
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let mut handle = setup.client(&runtime, 1024);
+        let _channel = runtime.block_on(async {
+            assert!(handle.authenticate_none("viewer").await.unwrap().success());
+            let mut channel = handle.channel_open_session().await.unwrap();
+            channel.request_shell(true).await.unwrap();
+            while !matches!(channel.wait().await, Some(ChannelMsg::Success)) {}
+            channel
+        });
+        setup.establishing.join().unwrap().unwrap();
+
+        // the window of the client is exhausted, and never adjusted, since its runtime no longer runs
+        let writer = setup.ssh.try_clone().unwrap();
+        let writing = spawn(move || (&writer).write_all(&[b'x'; 1 << 16]));
+        sleep(Duration::from_millis(100));
+        assert!(!writing.is_finished());
+
+        // the writer gives up once the client is gone
+        setup.socket.shutdown(Shutdown::Both).unwrap();
+        let err = writing.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+        let err = (&setup.ssh).write(b"x").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,390 @@
+use socket2::{SockAddr, SockRef, Socket, Type};
+use std::{
+    io::{ErrorKind, Read, Result, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tls")]
+use super::{Certificate, TlsStream};
+
+#[cfg(feature = "ssh")]
+use super::{HostKey, SshStream};
+
+#[cfg(any(feature = "tls", feature = "ssh"))]
+use std::sync::Arc;
+
+#[cfg(unix)]
+use std::{
+    fs::{remove_file, set_permissions, symlink_metadata, Permissions},
+    io::Error,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+/// Address of every peer of a Unix domain socket, which no TCP peer can have
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// How long a listener may take to accept the connection that wakes it up
+const WAKE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A connection accepted by any listener
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+    #[cfg(feature = "ssh")]
+    Ssh(SshStream),
+}
+
+impl Stream {
+    /// Address of the peer, which is [`UNIX_PEER`] for Unix domain sockets.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        match self {
+            Self::Tcp(s) => s.peer_addr(),
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(UNIX_PEER),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.socket().peer_addr(),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s) => s.socket().peer_addr(),
+        }
+    }
+
+    /// Address the peer connected to, if it has one.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(s) => s.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.socket().local_addr().ok(),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s) => s.socket().local_addr().ok(),
+        }
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_read_timeout(dur),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_read_timeout(dur),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.socket().set_read_timeout(dur),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s) => s.socket().set_read_timeout(dur),
+        }
+    }
+
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_write_timeout(dur),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_write_timeout(dur),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.socket().set_write_timeout(dur),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s) => s.socket().set_write_timeout(dur),
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Tcp(s) => Self::Tcp(s.try_clone()?),
+            #[cfg(unix)]
+            Self::Unix(s) => Self::Unix(s.try_clone()?),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => Self::Tls(s.try_clone()?),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s) => Self::Ssh(s.try_clone()?),
+        })
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        match self {
+            Self::Tcp(s) => s.shutdown(how),
+            #[cfg(unix)]
+            Self::Unix(s) => s.shutdown(how),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.shutdown(how),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s) => s.shutdown(how),
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Stream::Tcp(s) => (&*s).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => (&*s).read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => (&*s).read(buf),
+            #[cfg(feature = "ssh")]
+            Stream::Ssh(s) => (&*s).read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Stream::Tcp(s) => (&*s).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => (&*s).write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => (&*s).write(buf),
+            #[cfg(feature = "ssh")]
+            Stream::Ssh(s) => (&*s).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Stream::Tcp(s) => (&*s).flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => (&*s).flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => (&*s).flush(),
+            #[cfg(feature = "ssh")]
+            Stream::Ssh(s) => (&*s).flush(),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// A connection just accepted by a listener, on which no TLS or SSH session has begun yet
+pub enum Incoming {
+    Plain(Stream),
+    #[cfg(feature = "tls")]
+    Tls(TcpStream, Arc<Certificate>),
+    #[cfg(feature = "ssh")]
+    Ssh(TcpStream, Arc<HostKey>),
+}
+
+impl Incoming {
+    /// Bound how long the peer has to send anything and to take whatever is sent to it,
+    /// which carries over to the stream it begins.
+    pub fn set_timeouts(&self, dur: Option<Duration>) -> Result<()> {
+        match self {
+            Self::Plain(s) => {
+                s.set_read_timeout(dur)?;
+                s.set_write_timeout(dur)
+            }
+            #[cfg(feature = "tls")]
+            Self::Tls(s, _) => {
+                s.set_read_timeout(dur)?;
+                s.set_write_timeout(dur)
+            }
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s, _) => {
+                s.set_read_timeout(dur)?;
+                s.set_write_timeout(dur)
+            }
+        }
+    }
+
+    /// Begin the TLS or SSH session, if any, where the TLS handshake has to be complete by the deadline
+    /// and the SSH handshake happens along with the viewer.
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    pub fn begin(self, deadline: Instant) -> Result<Stream> {
+        Ok(match self {
+            Self::Plain(s) => s,
+            #[cfg(feature = "tls")]
+            Self::Tls(s, cert) => Stream::Tls(cert.accept(s, deadline)?),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s, key) => Stream::Ssh(key.accept(s)?),
+        })
+    }
+}
+
+/// Connections whose reads can be bounded in time
+pub trait Timeout {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()>;
+}
+
+impl Timeout for Stream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        Stream::set_read_timeout(self, dur)
+    }
+}
+
+impl Timeout for Incoming {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        match self {
+            Self::Plain(s) => s.set_read_timeout(dur),
+            #[cfg(feature = "tls")]
+            Self::Tls(s, _) => s.set_read_timeout(dur),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(s, _) => s.set_read_timeout(dur),
+        }
+    }
+}
+
+/// Reads of a connection that have to be done by a deadline, however the peer spreads out what it sends
+pub struct Timed<'a, T> {
+    inner: &'a T,
+    deadline: Instant,
+}
+
+impl<'a, T> Timed<'a, T> {
+    pub const fn new(inner: &'a T, deadline: Instant) -> Self {
+        Self { inner, deadline }
+    }
+}
+
+/// Every read only waits for whatever time is left, failing with `TimedOut` once there's none.
+impl<'a, T: Timeout> Read for Timed<'a, T>
+where
+    &'a T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.inner.set_read_timeout(Some(left))?;
+        let mut inner = self.inner;
+        match inner.read(buf) {
+            // the timeout of the socket itself shows up as `WouldBlock` on Unix
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Err(ErrorKind::TimedOut.into()),
+            res => res,
+        }
+    }
+}
+
+/// Reads come straight off the connection, ahead of any TLS or SSH handshake.
+impl Read for &Incoming {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Incoming::Plain(s) => (&*s).read(buf),
+            #[cfg(feature = "tls")]
+            Incoming::Tls(s, _) => (&*s).read(buf),
+            #[cfg(feature = "ssh")]
+            Incoming::Ssh(s, _) => (&*s).read(buf),
+        }
+    }
+}
+
+/// A listener of either TCP, a Unix domain socket, TLS or SSH
+pub enum Server {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    #[cfg(feature = "tls")]
+    Tls(TcpListener, Arc<Certificate>),
+    #[cfg(feature = "ssh")]
+    Ssh(TcpListener, Arc<HostKey>),
+}
+
+impl Server {
+    /// Accept the next connection, along with the address of its peer.
+    pub fn accept(&self) -> Result<(Incoming, SocketAddr)> {
+        match self {
+            Self::Tcp(l) => l
+                .accept()
+                .map(|(s, addr)| (Incoming::Plain(Stream::Tcp(s)), addr)),
+            #[cfg(unix)]
+            Self::Unix(l) => l
+                .accept()
+                .map(|(s, _)| (Incoming::Plain(Stream::Unix(s)), UNIX_PEER)),
+            #[cfg(feature = "tls")]
+            Self::Tls(l, cert) => l
+                .accept()
+                .map(|(s, addr)| (Incoming::Tls(s, cert.clone()), addr)),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(l, key) => l
+                .accept()
+                .map(|(s, addr)| (Incoming::Ssh(s, key.clone()), addr)),
+        }
+    }
+
+    /// Port the listener is bound to, or `None` for a Unix domain socket.
+    pub fn port(&self) -> Result<Option<u16>> {
+        match self {
+            Self::Tcp(l) => l.local_addr().map(|addr| Some(addr.port())),
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(None),
+            #[cfg(feature = "tls")]
+            Self::Tls(l, _) => l.local_addr().map(|addr| Some(addr.port())),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(l, _) => l.local_addr().map(|addr| Some(addr.port())),
+        }
+    }
+
+    /// Another handle onto the same listener, through which it can be closed from another thread.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Tcp(l) => Self::Tcp(l.try_clone()?),
+            #[cfg(unix)]
+            Self::Unix(l) => Self::Unix(l.try_clone()?),
+            #[cfg(feature = "tls")]
+            Self::Tls(l, cert) => Self::Tls(l.try_clone()?, cert.clone()),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(l, key) => Self::Ssh(l.try_clone()?, key.clone()),
+        })
+    }
+
+    /// Stop accepting connections, which wakes up whichever thread is blocked on [`Server::accept`].
+    pub fn close(&self) -> Result<()> {
+        let socket = match self {
+            Self::Tcp(l) => SockRef::from(l),
+            #[cfg(unix)]
+            Self::Unix(l) => SockRef::from(l),
+            #[cfg(feature = "tls")]
+            Self::Tls(l, _) => SockRef::from(l),
+            #[cfg(feature = "ssh")]
+            Self::Ssh(l, _) => SockRef::from(l),
+        };
+
+        // connecting wakes the listener up anywhere, whereas shutting it down only does on Linux
+        if let Ok(mut addr) = socket.local_addr() {
+            if let Some(local) = addr.as_socket().filter(|a| a.ip().is_unspecified()) {
+                let loopback = match local.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                };
+                addr = SockAddr::from(SocketAddr::new(loopback, local.port()))
+            }
+            if let Ok(waker) = Socket::new(addr.domain(), Type::STREAM, None) {
+                _ = waker.connect_timeout(&addr, WAKE_TIMEOUT)
+            }
+        }
+        match socket.shutdown(Shutdown::Both) {
+            Err(e) if e.kind() == ErrorKind::NotConnected => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Bind a Unix domain socket with the given file mode, replacing the socket a previous process left behind.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, mode: u32) -> Result<Self> {
+        if let Ok(meta) = symlink_metadata(path) {
+            // only ever remove a socket, and only if nothing is listening on it anymore
+            if !meta.file_type().is_socket() {
+                return Err(Error::new(ErrorKind::AlreadyExists, "not a socket"));
+            } else if UnixStream::connect(path).is_ok() {
+                return Err(Error::new(ErrorKind::AddrInUse, "socket in use"));
+            }
+            remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        set_permissions(path, Permissions::from_mode(mode))?;
+        Ok(Self::Unix(listener))
+    }
+}
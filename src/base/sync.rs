@@ -1,5 +1,12 @@
 use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 /// A concurrency-safe wrapper, conveniently bundled with its respective condition variable
 #[derive(Default)]
@@ -9,7 +16,7 @@ pub struct SignalLock<T> {
 
 impl<T> SignalLock<T> {
     /// Acquires the mutex for the boolean predicate
-    pub fn lock(&self) -> MutexGuard<bool> {
+    pub fn lock(&self) -> MutexGuard<'_, bool> {
         self.inner.1.lock()
     }
 
@@ -41,3 +48,213 @@ impl<T> std::ops::Deref for SignalLock<T> {
         &self.inner.0
     }
 }
+
+/// Raised once the server begins to shut down, so that every long-running thread stops after its current iteration
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Let every thread know to stop.
+    pub fn begin(&self) {
+        self.0.store(true, Ordering::Release)
+    }
+
+    /// Determinant for whether the server is shutting down.
+    pub fn is_begun(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// When a distributor last made progress, so that a stalled broadcast can be told apart from an idle one
+#[derive(Default)]
+pub struct Heartbeat {
+    last: Mutex<Option<Instant>>,
+}
+
+impl Heartbeat {
+    /// Record progress.
+    pub fn beat(&self) {
+        *self.last.lock() = Some(Instant::now())
+    }
+
+    /// Record that there is nothing to make progress on.
+    pub fn rest(&self) {
+        *self.last.lock() = None
+    }
+
+    /// Determinant for whether there was progress within the given duration, or nothing to make progress on.
+    pub fn is_alive(&self, within: Duration) -> bool {
+        self.last.lock().is_none_or(|t| t.elapsed() < within)
+    }
+}
+
+/// Keeps a shared counter incremented for as long as the guard is alive
+pub struct CounterGuard(Arc<AtomicUsize>);
+
+impl CounterGuard {
+    /// Increment the counter, unless it has already reached the maximum.
+    pub fn acquire(counter: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        counter
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Self(counter.clone()))
+    }
+}
+
+impl Drop for CounterGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The outcome of pushing onto a [`Queue`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Push {
+    /// The item was queued behind the existing backlog
+    Queued,
+    /// The backlog was discarded in favor of the item, yielding the number
+    /// of items discarded since the consumer last made progress
+    Dropped(usize),
+    /// The queue has been closed, so the item was discarded
+    Closed,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    dropped: usize,
+    closed: bool,
+}
+
+/// A bounded, single-consumer queue that skips ahead to the newest item once full
+pub struct Queue<T> {
+    inner: Arc<(Mutex<QueueState<T>>, Condvar)>,
+    capacity: usize,
+}
+
+impl<T> Queue<T> {
+    pub fn new(capacity: usize) -> Self {
+        let state = QueueState {
+            items: VecDeque::with_capacity(capacity),
+            dropped: 0,
+            closed: false,
+        };
+        Self {
+            inner: Arc::new((Mutex::new(state), Condvar::new())),
+            capacity,
+        }
+    }
+
+    /// Appends an item, discarding the backlog if the queue is full
+    pub fn push(&self, item: T) -> Push {
+        let (lock, cvar) = &*self.inner;
+        let mut state = lock.lock();
+
+        if state.closed {
+            return Push::Closed;
+        }
+
+        let res = if state.items.len() >= self.capacity {
+            state.dropped += state.items.len();
+            state.items.clear();
+            Push::Dropped(state.dropped)
+        } else {
+            Push::Queued
+        };
+
+        state.items.push_back(item);
+        cvar.notify_one();
+        res
+    }
+
+    /// Blocks until an item is available, or returns `None` once the queue is closed
+    pub fn pop(&self) -> Option<T> {
+        let (lock, cvar) = &*self.inner;
+        let mut state = lock.lock();
+        cvar.wait_while(&mut state, |s| s.items.is_empty() && !s.closed);
+
+        if state.closed {
+            return None;
+        }
+        state.dropped = 0;
+        state.items.pop_front()
+    }
+
+    /// Wakes up the consumer and rejects any further items
+    pub fn close(&self) {
+        let (lock, cvar) = &*self.inner;
+        lock.lock().closed = true;
+        cvar.notify_all();
+    }
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_guard() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let first = CounterGuard::acquire(&counter, 2).unwrap();
+        let second = CounterGuard::acquire(&counter, 2).unwrap();
+        assert!(CounterGuard::acquire(&counter, 2).is_none());
+        assert_eq!(counter.load(Ordering::Acquire), 2);
+
+        // every guard gives its place back once dropped
+        drop(first);
+        let _third = CounterGuard::acquire(&counter, 2).unwrap();
+        drop(second);
+        assert_eq!(counter.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn queue_skips_ahead() {
+        let queue = Queue::new(2);
+        assert_eq!(queue.push(0), Push::Queued);
+        assert_eq!(queue.push(1), Push::Queued);
+
+        // a full queue discards its backlog for the newest item
+        assert_eq!(queue.push(2), Push::Dropped(2));
+        assert_eq!(queue.push(3), Push::Queued);
+        assert_eq!(queue.push(4), Push::Dropped(4));
+        assert_eq!(queue.pop(), Some(4));
+
+        // the count starts over once the consumer makes progress
+        assert_eq!(queue.push(5), Push::Queued);
+        assert_eq!(queue.push(6), Push::Queued);
+        assert_eq!(queue.push(7), Push::Dropped(2));
+        assert_eq!(queue.pop(), Some(7));
+    }
+
+    #[test]
+    fn closed_queue() {
+        let queue = Queue::new(2);
+        queue.push(0);
+        let consumer = queue.clone();
+        queue.close();
+
+        // whatever was left behind is never handed out
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(queue.push(1), Push::Closed);
+    }
+
+    #[test]
+    fn close_wakes_consumer() {
+        let queue = Queue::<usize>::new(2);
+        let consumer = queue.clone();
+        let popping = std::thread::spawn(move || consumer.pop());
+        std::thread::sleep(Duration::from_millis(50));
+        queue.close();
+        assert_eq!(popping.join().unwrap(), None);
+    }
+}
@@ -0,0 +1,237 @@
+use log::{trace, warn};
+use std::{
+    env::var,
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use socket2::{SockRef, Socket, Type};
+
+#[cfg(unix)]
+use std::{
+    env::{remove_var, var_os},
+    io::{Error, ErrorKind},
+    os::{
+        fd::{BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::{
+            ffi::OsStrExt,
+            net::{UnixDatagram, UnixListener},
+        },
+    },
+    path::{Path, PathBuf},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+
+use super::{bind, Channel, Result, Server, Shutdown};
+
+/// First descriptor of the sockets passed down by systemd, right after stdin, stdout and stderr
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Listening sockets passed down by systemd (socket activation), which are taken instead of binding their addresses
+#[derive(Default)]
+pub struct Activation {
+    tcp: Vec<TcpListener>,
+    #[cfg(unix)]
+    unix: Vec<(PathBuf, UnixListener)>,
+}
+
+impl Activation {
+    /// Take over the sockets of `LISTEN_FDS`, as long as `LISTEN_PID` is this very process.
+    #[cfg(unix)]
+    pub fn from_env() -> Result<Self> {
+        let pid = var("LISTEN_PID").ok().and_then(|p| p.parse::<u32>().ok());
+        let count = var("LISTEN_FDS").ok().and_then(|n| n.parse::<RawFd>().ok());
+
+        // the sockets are never passed on any further
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            remove_var(name)
+        }
+
+        let mut activation = Self::default();
+        let (Some(pid), Some(count)) = (pid, count) else {
+            return Ok(activation);
+        };
+        if pid != std::process::id() {
+            return Ok(activation);
+        }
+
+        let Some(end) = LISTEN_FDS_START.checked_add(count).filter(|_| count >= 0) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("LISTEN_FDS={} is out of range", count),
+            )
+            .into());
+        };
+
+        for fd in LISTEN_FDS_START..end {
+            // SAFETY: systemd passes its sockets on the consecutive descriptors from 3 onwards,
+            // which are only borrowed until they turn out to be listening sockets
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let socket = SockRef::from(&borrowed);
+            socket.set_cloexec(true)?;
+            if socket.r#type()? != Type::STREAM || !socket.is_listener()? {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("socket {} is not a listening stream socket", fd),
+                )
+                .into());
+            }
+            // SAFETY: the descriptor is an open socket, which nothing else owns
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+
+            let addr = socket.local_addr()?;
+            if let Some(addr) = addr.as_socket() {
+                trace!("Activated @ {}", addr);
+                activation.tcp.push(socket.into())
+            } else if let Some(path) = addr.as_pathname() {
+                trace!("Activated @ unix:{}", path.display());
+                let path = path.to_owned();
+                activation
+                    .unix
+                    .push((path, UnixListener::from(OwnedFd::from(socket))))
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("socket {} is neither TCP nor a Unix domain socket", fd),
+                )
+                .into());
+            }
+        }
+        Ok(activation)
+    }
+
+    /// Sockets can only be passed down on Unix.
+    #[cfg(not(unix))]
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    /// The passed socket of the given address, or else of its port, otherwise a newly bound one.
+    /// Matching by port alone lets `ListenStream=PORT` serve `--addr`, though on whichever addresses systemd bound.
+    pub fn bind(&mut self, addr: SocketAddr) -> Result<TcpListener> {
+        let local = |l: &TcpListener| l.local_addr().ok();
+        if let Some(i) = self.tcp.iter().position(|l| local(l) == Some(addr)) {
+            return Ok(self.tcp.remove(i));
+        }
+        match self
+            .tcp
+            .iter()
+            .position(|l| local(l).is_some_and(|a| a.port() == addr.port()))
+        {
+            Some(i) => {
+                let listener = self.tcp.remove(i);
+                if let Some(local) = local(&listener) {
+                    warn!("Serving {} on the passed socket of {}", addr, local);
+                }
+                Ok(listener)
+            }
+            None => bind(addr),
+        }
+    }
+
+    /// The passed socket of the given path, otherwise a newly bound one with the given file mode.
+    #[cfg(unix)]
+    pub fn bind_unix(&mut self, path: &Path, mode: u32) -> Result<Server> {
+        match self.unix.iter().position(|(p, _)| p == path) {
+            Some(i) => Ok(Server::Unix(self.unix.remove(i).1)),
+            None => Ok(Server::bind_unix(path, mode)?),
+        }
+    }
+
+    /// Every passed socket that no address or path was given for.
+    pub fn into_servers(self) -> Vec<Server> {
+        let servers = self.tcp.into_iter().map(Server::Tcp);
+        #[cfg(unix)]
+        let servers = servers.chain(self.unix.into_iter().map(|(_, l)| Server::Unix(l)));
+        servers.collect()
+    }
+}
+
+/// Connection to systemd, which is told about the state of the service (`sd_notify`) if it asked to be told
+#[derive(Clone, Default)]
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: Option<Arc<UnixDatagram>>,
+}
+
+impl Notifier {
+    /// Connect to `NOTIFY_SOCKET`, if set, so that it stays reachable once the process is confined.
+    #[cfg(unix)]
+    pub fn from_env() -> Result<Self> {
+        let Some(path) = var_os("NOTIFY_SOCKET") else {
+            return Ok(Self::default());
+        };
+        let socket = UnixDatagram::unbound()?;
+        match path.as_bytes().strip_prefix(b"@") {
+            // a socket of the abstract namespace
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                socket.connect_addr(&std::os::unix::net::SocketAddr::from_abstract_name(name)?)?
+            }
+            _ => socket.connect(path)?,
+        };
+        Ok(Self {
+            socket: Some(Arc::new(socket)),
+        })
+    }
+
+    /// There is no systemd to notify outside of Unix.
+    #[cfg(not(unix))]
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    /// Let systemd know about the given state, such as `READY=1`.
+    #[cfg(unix)]
+    pub fn notify(&self, state: &str) -> Result {
+        if let Some(socket) = &self.socket {
+            socket.send(state.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// There is no systemd to notify outside of Unix.
+    #[cfg(not(unix))]
+    pub fn notify(&self, _: &str) -> Result {
+        Ok(())
+    }
+}
+
+/// Interval of the watchdog of systemd, if it is enabled for this very process.
+fn watchdog_interval() -> Option<Duration> {
+    let usec = var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    match var("WATCHDOG_PID").ok().map(|p| p.parse::<u32>()) {
+        Some(Ok(pid)) if pid != std::process::id() => None,
+        _ => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// Ping the watchdog of systemd twice per interval, for as long as the broadcast of every channel keeps up.
+pub fn watchdog_handler(channels: Arc<[Arc<Channel>]>, notifier: Notifier, shutdown: &Shutdown) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    let shutdown = shutdown.clone();
+    spawn(move || {
+        while !shutdown.is_begun() {
+            match channels.iter().find(|c| !c.heartbeat().is_alive(interval)) {
+                Some(c) => warn!(
+                    "Withheld the watchdog ping: the broadcast of {} stalled",
+                    c.path()
+                ),
+                None => {
+                    if let Err(e) = notifier.notify("WATCHDOG=1") {
+                        warn!("Failed to ping the watchdog: {}", e)
+                    }
+                }
+            }
+            sleep(interval / 2)
+        }
+    });
+}
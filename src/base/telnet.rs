@@ -0,0 +1,312 @@
+use parking_lot::Mutex;
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread::spawn,
+    time::{Duration, Instant},
+};
+
+use super::{raw_viewer, Config, Mount, Response, Result, Stream, Timed, Viewer};
+
+/// Commands of the telnet protocol (RFC 854)
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const IP: u8 = 244;
+const SE: u8 = 240;
+
+/// Options negotiated with every client
+const ECHO: u8 = 1;
+const SGA: u8 = 3;
+const NAWS: u8 = 31;
+
+/// How long a client has to report the size of its terminal before the first frame
+const NAWS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Something the client sent, once it is complete
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    Key(u8),
+    Resize(u16, u16),
+    /// The client won't report the size of its terminal
+    Refused,
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Default)]
+enum State {
+    #[default]
+    Data,
+    Command,
+    Option(u8),
+    Sub,
+    SubCommand,
+}
+
+/// Separates the commands of a client from its keys, across as many reads as it takes
+#[derive(Default)]
+struct Parser {
+    state: State,
+    sub: Vec<u8>,
+}
+
+impl Parser {
+    /// Longest subnegotiation that is kept, where a window size takes up 5 bytes
+    const MAX_SUB: usize = 16;
+
+    fn feed(&mut self, b: u8) -> Option<Input> {
+        let (state, input) = match (self.state, b) {
+            (State::Data, IAC) => (State::Command, None),
+            (State::Data, _) => (State::Data, Some(Input::Key(b))),
+            (State::Command, IAC) => (State::Data, Some(Input::Key(IAC))),
+            (State::Command, IP) => (State::Data, Some(Input::Interrupt)),
+            (State::Command, WILL..=DONT) => (State::Option(b), None),
+            (State::Command, SB) => {
+                self.sub.clear();
+                (State::Sub, None)
+            }
+            (State::Command, _) => (State::Data, None),
+            (State::Option(WONT), NAWS) => (State::Data, Some(Input::Refused)),
+            (State::Option(_), _) => (State::Data, None),
+            (State::Sub, IAC) => (State::SubCommand, None),
+            (State::SubCommand, SE) => (State::Data, self.subnegotiation()),
+            // anything else within a subnegotiation is data, including an escaped `IAC`
+            (State::Sub | State::SubCommand, _) => {
+                if self.sub.len() < Self::MAX_SUB {
+                    self.sub.push(b)
+                }
+                (State::Sub, None)
+            }
+        };
+        self.state = state;
+        input
+    }
+
+    /// The completed subnegotiation, where only the window size is of interest.
+    fn subnegotiation(&self) -> Option<Input> {
+        match self.sub[..] {
+            [NAWS, w0, w1, h0, h1] => Some(Input::Resize(
+                u16::from_be_bytes([w0, w1]),
+                u16::from_be_bytes([h0, h1]),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Size of the terminal of a telnet or SSH client, as last reported through NAWS (RFC 1073) or a `window-change` request
+pub struct Window {
+    size: AtomicU32,
+    parser: Mutex<Parser>,
+    hung_up: AtomicBool,
+}
+
+impl Window {
+    /// Size assumed until the client reports its own
+    const DEFAULT: (u16, u16) = (80, 24);
+
+    /// Hang up once the client types `Ctrl-C`, `Ctrl-D` or `q`, which ends the stream along with the next frame.
+    pub fn hang_up(&self, keys: &[u8]) -> bool {
+        let is_hung_up = keys.iter().any(|key| matches!(key, 3 | 4 | b'q'));
+        if is_hung_up {
+            self.hung_up.store(true, Ordering::Release)
+        }
+        is_hung_up
+    }
+
+    /// Determinant for whether the client asked for the stream to end.
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::Acquire)
+    }
+
+    /// Columns and rows of the terminal.
+    pub fn size(&self) -> (usize, usize) {
+        let size = self.size.load(Ordering::Acquire);
+        ((size >> 16) as usize, (size & 0xFFFF) as usize)
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) {
+        // zero means the client doesn't know either
+        if cols > 0 && rows > 0 {
+            self.size
+                .store(u32::from(cols) << 16 | u32::from(rows), Ordering::Release)
+        }
+    }
+
+    /// Apply every resize among the bytes the client sent, returning everything they contained.
+    fn input(&self, bytes: &[u8]) -> Vec<Input> {
+        let mut parser = self.parser.lock();
+        let inputs = bytes
+            .iter()
+            .filter_map(|b| parser.feed(*b))
+            .collect::<Vec<_>>();
+        for input in &inputs {
+            if let Input::Resize(cols, rows) = input {
+                self.resize(*cols, *rows)
+            }
+        }
+        inputs
+    }
+
+    /// Ask the client to report the size of its terminal and to leave echoing and line buffering to the server,
+    /// then wait for the size until the deadline.
+    fn negotiate(mut stream: &Stream, deadline: Instant) -> Result<Arc<Self>> {
+        stream.write_all(&[IAC, WILL, ECHO, IAC, WILL, SGA, IAC, DO, NAWS])?;
+        let window = Arc::new(Self::default());
+
+        let mut buf = [0; 64];
+        loop {
+            let bytes = match Timed::new(stream, deadline).read(&mut buf) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(bytes) => bytes,
+                // clients that don't speak telnet, such as `nc`, keep the default size
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e.into()),
+            };
+            if window
+                .input(&buf[..bytes])
+                .iter()
+                .any(|i| matches!(i, Input::Resize(..) | Input::Refused))
+            {
+                break;
+            }
+        }
+        Ok(window)
+    }
+
+    /// Keep reading whatever the client types on a thread of its own, until it disconnects or hangs up.
+    pub fn listen(self: Arc<Self>, stream: Stream) {
+        spawn(move || {
+            let mut buf = [0; 64];
+            while let Ok(bytes @ 1..) = (&stream).read(&mut buf) {
+                // an interrupt is as good as `Ctrl-C`
+                let keys = self
+                    .input(&buf[..bytes])
+                    .into_iter()
+                    .filter_map(|i| match i {
+                        Input::Key(key) => Some(key),
+                        Input::Interrupt => Some(3),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                if self.hang_up(&keys) {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        let (cols, rows) = Self::DEFAULT;
+        Self {
+            size: AtomicU32::new(u32::from(cols) << 16 | u32::from(rows)),
+            parser: Mutex::default(),
+            hung_up: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Stream the animation of the raw port to a telnet client, sized to its terminal if it reports it by the deadline.
+pub fn telnet_viewer(
+    stream: &Stream,
+    peer: SocketAddr,
+    mounts: &[Mount],
+    cfg: &Config,
+    deadline: Instant,
+) -> Result<Viewer> {
+    let window = Window::negotiate(stream, deadline.min(Instant::now() + NAWS_TIMEOUT))?;
+
+    Ok(Viewer {
+        response: Response::Telnet,
+        window: Some(window),
+        ..raw_viewer(peer, mounts, cfg)?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A window reporting the given size, as sent by the client.
+    fn naws(cols: u16, rows: u16) -> Vec<u8> {
+        let [c0, c1] = cols.to_be_bytes();
+        let [r0, r1] = rows.to_be_bytes();
+        // an `IAC` within the size is escaped by doubling it
+        let size = [c0, c1, r0, r1]
+            .into_iter()
+            .flat_map(|b| match b {
+                IAC => vec![IAC, IAC],
+                b => vec![b],
+            })
+            .collect::<Vec<_>>();
+        [&[IAC, SB, NAWS][..], &size, &[IAC, SE]].concat()
+    }
+
+    #[test]
+    fn escaped_iac() {
+        let window = Window::default();
+        assert_eq!(
+            window.input(&[b'a', IAC, IAC, b'b']),
+            [Input::Key(b'a'), Input::Key(IAC), Input::Key(b'b')]
+        );
+        // split across reads
+        assert!(window.input(&[IAC]).is_empty());
+        assert_eq!(window.input(&[IAC]), [Input::Key(IAC)]);
+    }
+
+    #[test]
+    fn commands() {
+        let window = Window::default();
+        assert_eq!(
+            window.input(&[IAC, DO, ECHO, IAC, WONT, NAWS, IAC, IP, b'q']),
+            [Input::Refused, Input::Interrupt, Input::Key(b'q')]
+        );
+    }
+
+    #[test]
+    fn naws_across_reads() {
+        for size in [(120, 40), (255, 255), (0x1FF, 0xFF00)] {
+            let bytes = naws(size.0, size.1);
+            for i in 0..bytes.len() {
+                let window = Window::default();
+                let mut inputs = window.input(&bytes[..i]);
+                inputs.extend(window.input(&bytes[i..]));
+                assert_eq!(inputs, [Input::Resize(size.0, size.1)], "{}", i);
+                assert_eq!(window.size(), (usize::from(size.0), usize::from(size.1)));
+            }
+        }
+
+        // a size of zero keeps the last one
+        let window = Window::default();
+        window.input(&naws(0, 0));
+        assert_eq!(window.size(), (80, 24));
+    }
+
+    #[test]
+    fn truncated_subnegotiation() {
+        let window = Window::default();
+
+        // a window size missing its rows is ignored, while what follows is read as usual
+        assert_eq!(
+            window.input(&[IAC, SB, NAWS, 0, 100, IAC, SE, b'a']),
+            [Input::Key(b'a')]
+        );
+        assert_eq!(window.size(), (80, 24));
+
+        // an endless subnegotiation takes up no more than its limit
+        window.input(&[IAC, SB, NAWS]);
+        window.input(&[0; 1000]);
+        assert_eq!(window.parser.lock().sub.len(), Parser::MAX_SUB);
+        assert_eq!(window.input(&[IAC, SE, b'b']), [Input::Key(b'b')]);
+        assert_eq!(window.size(), (80, 24));
+    }
+}
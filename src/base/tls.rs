@@ -0,0 +1,156 @@
+use parking_lot::{Mutex, RwLock};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use super::Result;
+
+/// The certificate of a TLS listener, which can be reloaded from its files
+pub struct Certificate {
+    cert: PathBuf,
+    key: PathBuf,
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Certificate {
+    /// Read the PEM files of the certificate chain and its private key.
+    fn read(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+        let certs = CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(key)?;
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+
+    pub fn new(cert: &Path, key: &Path) -> Result<Self> {
+        Ok(Self {
+            config: RwLock::new(Self::read(cert, key)?),
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+        })
+    }
+
+    /// Replace the certificate with the current contents of its files, which only affects new connections.
+    pub fn reload(&self) -> Result {
+        *self.config.write() = Self::read(&self.cert, &self.key)?;
+        Ok(())
+    }
+
+    /// Begin the server side of a connection, once the handshake is complete by the deadline.
+    pub fn accept(&self, mut tcp: TcpStream, deadline: Instant) -> std::io::Result<TlsStream> {
+        let mut conn =
+            ServerConnection::new(self.config.read().clone()).map_err(std::io::Error::other)?;
+
+        // every read only waits for whatever time is left, however the peer spreads out the handshake
+        while conn.is_handshaking() {
+            if conn.wants_write() {
+                conn.write_tls(&mut tcp)?;
+                continue;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            tcp.set_read_timeout(Some(left))?;
+            if conn.read_tls(&mut tcp)? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            // let the peer know what went wrong, if anything
+            if let Err(e) = conn.process_new_packets() {
+                _ = conn.write_tls(&mut tcp);
+                return Err(std::io::Error::new(ErrorKind::InvalidData, e));
+            }
+        }
+        while conn.wants_write() {
+            conn.write_tls(&mut tcp)?;
+        }
+
+        Ok(TlsStream {
+            socket: tcp.try_clone()?,
+            inner: Arc::new(Mutex::new(StreamOwned::new(conn, tcp))),
+        })
+    }
+}
+
+/// A TLS connection, which can be shared between the threads reading, writing and disconnecting it
+pub struct TlsStream {
+    inner: Arc<Mutex<StreamOwned<ServerConnection, TcpStream>>>,
+    socket: TcpStream,
+}
+
+impl TlsStream {
+    /// The underlying TCP socket.
+    pub const fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            inner: self.inner.clone(),
+            socket: self.socket.try_clone()?,
+        })
+    }
+
+    /// Let the peer know nothing more is sent, unless another thread is still writing, then shut the socket down.
+    /// Peers often close the connection as soon as the response is complete, which is not an error.
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        if how != Shutdown::Read {
+            if let Some(mut stream) = self.inner.try_lock() {
+                stream.conn.send_close_notify();
+                _ = stream.flush();
+            }
+        }
+        match self.socket.shutdown(how) {
+            Err(e) if e.kind() == ErrorKind::NotConnected => Ok(()),
+            res => res,
+        }
+    }
+}
+
+/// Reads wait for the socket without holding onto the connection, so that a thread waiting for the peer
+/// doesn't hold up another one writing to it.
+impl Read for &TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.inner.lock().conn.reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                res => return res,
+            }
+
+            // a record may arrive over several reads, each of which only takes whatever is already there
+            self.socket.peek(&mut [0])?;
+            let mut stream = self.inner.lock();
+            let StreamOwned { conn, sock } = &mut *stream;
+            conn.read_tls(sock)?;
+            if let Err(e) = conn.process_new_packets() {
+                _ = conn.write_tls(sock);
+                return Err(std::io::Error::new(ErrorKind::InvalidData, e));
+            }
+            // such as the answer to a key update
+            while conn.wants_write() {
+                conn.write_tls(sock)?;
+            }
+        }
+    }
+}
+
+impl Write for &TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.lock().flush()
+    }
+}
@@ -1,70 +1,572 @@
-use httparse::{Request, EMPTY_HEADER};
-use log::debug;
+use httparse::{Header, Request, EMPTY_HEADER};
+use log::{debug, info, warn};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use socket2::{Domain, Socket, Type};
 use std::{
-    io::Read,
-    net::{IpAddr, SocketAddr, TcpStream},
-    str::FromStr,
-    thread::{spawn, JoinHandle},
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::{
+    client_addr, Config, Encoding, Error, Framing, Invalid, Mount, Response, Result, Shutdown,
+    Stream, Timed, Upgrade, UriError, Viewer,
 };
 
-use super::{Invalid, Result, UriError};
+/// How often a condition is checked while waiting on it
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
-/// Verify the potential client by checking if the User-Agent's product is `curl` and a few other practicalities
-pub fn verify_stream(mut stream: &TcpStream, uri_path: &str) -> Result<SocketAddr> {
-    // read from the incoming stream
-    let mut buf = [0; 128];
-    let bytes = stream.read(&mut buf)?;
+/// Stands in for the `User-Agent` of a request without one
+const NO_AGENT: Header = Header {
+    name: "User-Agent",
+    value: b"",
+};
 
-    // parse the request
-    let mut headers = [EMPTY_HEADER; 8];
+/// Verify the potential client by checking if the User-Agent and Accept headers are allowed and a few other practicalities,
+/// along with the channel of the requested path, the options of its query and the kind of response it accepts.
+/// Rejected requests are answered with an explanation of how to connect instead, while the request has to be
+/// complete by the deadline.
+pub fn verify_stream(
+    stream: &Stream,
+    peer: SocketAddr,
+    mounts: &[Mount],
+    cfg: &Config,
+    deadline: Instant,
+) -> Result<Viewer> {
+    // read then parse the request
+    let mut buf = Vec::new();
+    let mut headers = vec![EMPTY_HEADER; cfg.max_headers()];
     let mut req = Request::new(&mut headers);
-    _ = req.parse(&buf[..bytes])?;
-
-    // validate the request
-    if let (Some(method), Some(path), Some(version)) = (req.method, req.path, req.version) {
-        if method != "GET" {
-            Err(UriError::Method(method.to_owned()).into())
-        } else if path != uri_path {
-            Err(UriError::Path(path.to_owned()).into())
-        } else if version != 1 {
-            Err(UriError::Version(version).into())
-        // check for any incompatible headers
-        } else if let Some(h) = req.headers.iter().find(|h| match h.name {
-            "User-Agent" => !h.value.starts_with(b"curl"),
-            "Accept" => h.value != b"*/*",
-            _ => false,
-        }) {
-            Err(UriError::from(h).into())
-        } else {
-            // retrieve client IP-address and port number
-            let peer_addr = stream.peer_addr()?;
-
-            // attempt to parse real remote address, if specified
-            if let Some(remote_addr_header) = req.headers.iter().find(|h| h.name == "X-Real-IP") {
-                IpAddr::from_str(&String::from_utf8(remote_addr_header.value.to_vec())?)
-                    .map(|addr| SocketAddr::new(addr, peer_addr.port()))
-                    .map_err(Into::into)
-            // otherwise, return original addr
-            } else {
-                Ok(peer_addr)
+    let res = read_head(Timed::new(stream, deadline), &mut buf, cfg.max_head_size())
+        .and_then(|_| req.parse(&buf).map_err(Into::into))
+        .and_then(|_| validate(&req, peer, mounts, cfg));
+
+    // let the client know what went wrong
+    if let Some((status, headers, explanation)) = res.as_ref().err().and_then(rejection) {
+        // where the client can find the stream(s)
+        let host = req
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Host"))
+            .map_or_else(
+                || stream.local_addr().unwrap_or(cfg.addr()).to_string(),
+                |h| String::from_utf8_lossy(h.value).into_owned(),
+            );
+        let hints = mounts
+            .iter()
+            .map(|m| format!("try: curl {}{}", host, m.path()))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        reject(
+            stream,
+            status,
+            headers,
+            &format!("{}\n{}", explanation, hints),
+        )?;
+    }
+    res
+}
+
+/// Status line, additional headers and explanation of the response rejecting a request, if the error is the client's.
+fn rejection(err: &Error) -> Option<(&'static str, &'static str, String)> {
+    match err {
+        Error::Parse(Invalid::Uri(e)) => Some((e.status(), e.headers(), e.explain())),
+        Error::Parse(Invalid::Format) => Some((
+            "400 Bad Request",
+            "",
+            "The request is incomplete.".to_owned(),
+        )),
+        _ => None,
+    }
+}
+
+/// Read the head of a request, which may arrive over several reads, up until the empty line that ends it.
+fn read_head(mut stream: impl Read, buf: &mut Vec<u8>, max_size: usize) -> Result {
+    let mut chunk = [0; 1024];
+    loop {
+        let bytes = stream.read(&mut chunk)?;
+        if bytes == 0 {
+            return Err(Invalid::Format.into());
+        }
+
+        // the empty line may begin within the previous read
+        let from = buf.len().saturating_sub(2);
+        buf.extend_from_slice(&chunk[..bytes]);
+
+        match head_len(buf, from) {
+            Some(len) if len <= max_size => {
+                buf.truncate(len);
+                return Ok(());
             }
+            None if buf.len() < max_size => {}
+            _ => return Err(UriError::TooLarge(max_size).into()),
         }
+    }
+}
+
+/// Length of the head within `buf` if it is complete, searching from the given offset.
+/// Bare `\n` line endings are tolerated, as with `httparse`.
+fn head_len(buf: &[u8], from: usize) -> Option<usize> {
+    (from..buf.len())
+        .filter(|&i| buf[i] == b'\n')
+        .find_map(|i| match &buf[i + 1..] {
+            [b'\n', ..] => Some(i + 2),
+            [b'\r', b'\n', ..] => Some(i + 3),
+            _ => None,
+        })
+}
+
+/// Stream the animation of the raw port to the client, which skips HTTP entirely.
+pub fn raw_viewer(peer: SocketAddr, mounts: &[Mount], cfg: &Config) -> Result<Viewer> {
+    let mount = match cfg.raw_path() {
+        Some(path) => mounts.iter().find(|m| m.channel().path() == path),
+        None => mounts.first(),
+    };
+    Ok(Viewer {
+        addr: peer,
+        channel: mount.ok_or(Error::Empty)?.channel().clone(),
+        options: cfg.options(),
+        framing: Framing::Close,
+        encoding: Encoding::Identity,
+        response: Response::Raw,
+        window: None,
+    })
+}
+
+/// Validate a parsed request.
+fn validate(req: &Request, peer: SocketAddr, mounts: &[Mount], cfg: &Config) -> Result<Viewer> {
+    let (Some(method), Some(uri), Some(version)) = (req.method, req.path, req.version) else {
+        return Err(Invalid::Format.into());
+    };
+
+    // separate the path from the query
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+    // other versions than HTTP/1.0 and HTTP/1.1 are already rejected by `httparse`
+    if method != "GET" {
+        return Err(UriError::Method(method.to_owned()).into());
+    }
+
+    // find the channel served on the requested path
+    let Some(mount) = mounts.iter().find(|m| m.path() == path) else {
+        return Err(UriError::Path(path.to_owned()).into());
+    };
+
+    // custom frontends upgrade to a WebSocket or ask for events, while browsers navigating onto the path are served the player instead
+    let accepts = |media: &[u8]| {
+        req.headers
+            .iter()
+            .any(|h| h.name.eq_ignore_ascii_case("Accept") && contains(h.value, media))
+    };
+    let response = if let Some(upgrade) = Upgrade::from_headers(req.headers)? {
+        Response::WebSocket(upgrade)
+    } else if accepts(b"text/event-stream") {
+        Response::Events
+    } else if accepts(b"text/html") {
+        Response::Player
     } else {
-        Err(Invalid::Format.into())
+        Response::Stream
+    };
+
+    // check for any incompatible headers, where a missing `User-Agent` is judged as an empty one
+    let has_agent = req
+        .headers
+        .iter()
+        .any(|h| h.name.eq_ignore_ascii_case("User-Agent"));
+    let missing_agent = (!has_agent).then_some(&NO_AGENT);
+    if let Some(h) = req.headers.iter().chain(missing_agent).find(|h| {
+        if h.name.eq_ignore_ascii_case("User-Agent") {
+            !cfg.agents().allows_agent(h.value)
+        } else if h.name.eq_ignore_ascii_case("Accept") {
+            response == Response::Stream && !cfg.agents().allows_accept(h.value)
+        } else {
+            false
+        }
+    }) {
+        return Err(UriError::from(h).into());
+    }
+
+    // the actual address of the client, if the peer is a trusted proxy
+    let addr = client_addr(req.headers, peer, cfg.trusted_proxies())?;
+
+    // the compression of the response, if enabled and accepted
+    let encoding = req
+        .headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Accept-Encoding"))
+        .filter(|_| cfg.is_compressed())
+        .map_or(Encoding::Identity, |h| {
+            Encoding::negotiate(&String::from_utf8_lossy(h.value))
+        });
+
+    // a reconnecting `EventSource` resumes right after the last frame it received
+    let mut options = cfg.options().with_query(query)?;
+    if let Some(index) = req
+        .headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Last-Event-ID"))
+        .filter(|_| response == Response::Events)
+        .and_then(|h| std::str::from_utf8(h.value).ok()?.trim().parse().ok())
+    {
+        options = options.resuming_after(index)
+    }
+
+    Ok(Viewer {
+        addr,
+        channel: mount.channel().clone(),
+        options,
+        framing: Framing::from_version(version),
+        encoding,
+        response,
+        window: None,
+    })
+}
+
+/// Determinant for whether `needle` occurs anywhere within `haystack`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Respond with the given status and a plain-text explanation, before the connection is dropped.
+pub fn reject(mut stream: &Stream, status: &str, headers: &str, body: &str) -> Result {
+    stream.write_fmt(format_args!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}\n",
+        status,
+        body.len() + 1,
+        headers,
+        body
+    ))?;
+    Ok(())
+}
+
+/// Bind a listener, where IPv6 addresses only accept IPv6 so that an IPv4 address can be bound on the same port.
+pub fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
     }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
 }
 
-/// Spawn a new thread that repeatedly calls the provided function.
-pub fn init_handler(f: impl FnMut() -> Result + Send + 'static) -> JoinHandle<Result> {
-    spawn(move || loop_func(f))
+/// Reload something whenever the process receives `SIGHUP`, keeping what was loaded before on failure.
+pub fn reload_handler(name: &'static str, reload: impl Fn() -> Result + Send + 'static) -> Result {
+    let mut signals = Signals::new([SIGHUP])?;
+    spawn(move || {
+        for _ in signals.forever() {
+            match reload() {
+                Ok(()) => info!("Reloaded the {}", name),
+                Err(e) => warn!("Kept the previous {}: {}", name, e),
+            }
+        }
+    });
+    Ok(())
 }
 
-/// Continuously call the provided function while emitting errors.
-pub fn loop_func(mut f: impl FnMut() -> Result + Send + 'static) -> Result {
+/// Block until the process receives `SIGINT` or `SIGTERM`.
+pub fn shutdown_handler() -> Result {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    signals.forever().next();
+    Ok(())
+}
+
+/// Check the condition until it holds or the deadline passes, returning whether it holds.
+pub fn wait_until(deadline: Instant, mut f: impl FnMut() -> bool) -> bool {
     loop {
+        if f() {
+            return true;
+        } else if Instant::now() >= deadline {
+            return false;
+        }
+        sleep(POLL_INTERVAL)
+    }
+}
+
+/// Spawn a new thread that repeatedly calls the provided function, until the server shuts down.
+pub fn init_handler(
+    shutdown: &Shutdown,
+    f: impl FnMut() -> Result + Send + 'static,
+) -> JoinHandle<Result> {
+    let shutdown = shutdown.clone();
+    spawn(move || loop_func(&shutdown, f))
+}
+
+/// Continuously call the provided function while emitting errors, until the server shuts down.
+pub fn loop_func(shutdown: &Shutdown, mut f: impl FnMut() -> Result + Send + 'static) -> Result {
+    while !shutdown.is_begun() {
         // call the function
         if let Err(e) = f() {
             debug!("{}", e)
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use quickcheck::quickcheck;
+    use std::{
+        collections::VecDeque,
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use super::*;
+    use crate::base::{AsciiFrame, Channel, InitConfig};
+
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 40000);
+
+    const HEAD: &[u8] = b"GET /?speed=2 HTTP/1.1\r\nHost: donut.live\r\nUser-Agent: curl/8.5.0\r\nAccept: */*\r\n\r\n";
+
+    /// A stream handing out the given reads one after another, followed by the end of the stream.
+    struct Reads(VecDeque<Vec<u8>>);
+
+    impl Read for Reads {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some(mut read) = self.0.pop_front() else {
+                return Ok(0);
+            };
+            let n = read.len().min(buf.len());
+            buf[..n].copy_from_slice(&read[..n]);
+            if n < read.len() {
+                self.0.push_front(read.split_off(n))
+            }
+            Ok(n)
+        }
+    }
+
+    fn config(args: &[&str]) -> Config {
+        let args = ["donut-live"].iter().chain(args);
+        Config::from_init(InitConfig::parse_from(args)).unwrap()
+    }
+
+    /// Verify a request arriving over the given reads, the same way [`verify_stream`] does.
+    fn verify(reads: &[&[u8]], cfg: &Config) -> Result<Viewer> {
+        let frames = vec![AsciiFrame::new(
+            b"donut".to_vec(),
+            Duration::from_millis(50),
+        )];
+        let mounts = Mount::all(&[Arc::new(Channel::new("/", frames))]);
+
+        let mut buf = Vec::new();
+        let mut headers = vec![EMPTY_HEADER; cfg.max_headers()];
+        let mut req = Request::new(&mut headers);
+        let stream = Reads(reads.iter().map(|r| r.to_vec()).collect());
+        read_head(stream, &mut buf, cfg.max_head_size())
+            .and_then(|_| req.parse(&buf).map_err(Into::into))
+            .and_then(|_| validate(&req, PEER, &mounts, cfg))
+    }
+
+    /// Status line of the response rejecting the request, if it was rejected.
+    fn status(res: &Result<Viewer>) -> Option<&'static str> {
+        res.as_ref()
+            .err()
+            .and_then(rejection)
+            .map(|(status, ..)| status)
+    }
+
+    /// The head read from the given reads.
+    fn head(reads: &[&[u8]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let stream = Reads(reads.iter().map(|r| r.to_vec()).collect());
+        assert!(read_head(stream, &mut buf, 8192).is_ok());
+        buf
+    }
+
+    /// A request head of exactly the given length, padded out by a header of its own.
+    fn head_of(len: usize) -> Vec<u8> {
+        let mut head = b"GET / HTTP/1.1\r\nUser-Agent: curl/8.5.0\r\nX-Pad: ".to_vec();
+        head.resize(len - 4, b'a');
+        head.extend_from_slice(b"\r\n\r\n");
+        head
+    }
+
+    #[test]
+    fn trickled_head() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = Stream::Tcp(listener.accept().unwrap().0);
+
+        // a peer sending a byte every so often never lets a single read time out
+        let trickling = spawn(move || {
+            for byte in HEAD.iter().take(40) {
+                if peer.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                sleep(Duration::from_millis(50))
+            }
+        });
+        let start = Instant::now();
+        let mut buf = Vec::new();
+        let deadline = start + Duration::from_millis(300);
+        let res = read_head(Timed::new(&stream, deadline), &mut buf, 8192);
+        assert!(matches!(res, Err(Error::IO(e)) if e.kind() == std::io::ErrorKind::TimedOut));
+        assert!(start.elapsed() < Duration::from_millis(600));
+        assert!(!buf.is_empty());
+        drop(stream);
+        trickling.join().unwrap();
+    }
+
+    #[test]
+    fn split_at_every_offset() {
+        let cfg = config(&[]);
+        let bare = String::from_utf8_lossy(HEAD).replace("\r\n", "\n");
+        for head in [HEAD, bare.as_bytes()] {
+            for i in 1..head.len() {
+                assert!(verify(&[&head[..i], &head[i..]], &cfg).is_ok(), "{}", i);
+            }
+            let bytes = head.chunks(1).collect::<Vec<_>>();
+            assert!(verify(&bytes, &cfg).is_ok());
+        }
+    }
+
+    #[test]
+    fn line_endings() {
+        for (buf, len) in [
+            (&b"GET / HTTP/1.1\r\n\r\nbody"[..], Some(18)),
+            (b"GET / HTTP/1.1\n\nbody", Some(16)),
+            (b"GET / HTTP/1.1\r\n\nbody", Some(17)),
+            (b"GET / HTTP/1.1\n\r\nbody", Some(17)),
+            (b"GET / HTTP/1.1\r\n", None),
+            (b"GET / HTTP/1.1\r\n\r", None),
+            (b"GET / HTTP/1.1\r\rbody", None),
+        ] {
+            assert_eq!(head_len(buf, 0), len, "{:?}", String::from_utf8_lossy(buf));
+        }
+
+        // whatever follows the empty line is not part of the head
+        assert_eq!(head(&[b"GET / HTTP/1.1\n\nbody"]), b"GET / HTTP/1.1\n\n");
+    }
+
+    #[test]
+    fn terminator_straddling_reads() {
+        let line = &b"GET / HTTP/1.1\r\nUser-Agent: curl/8.5.0"[..];
+        for reads in [
+            [&b"\r\n\r"[..], b"\n"],
+            [b"\r\n", b"\r\n"],
+            [b"\r", b"\n\r\n"],
+            [b"\n", b"\n"],
+            [b"\n", b"\r\n"],
+            [b"\n\r", b"\n"],
+        ] {
+            let head = [line, reads[0], reads[1]].concat();
+            assert_eq!(self::head(&[line, reads[0], reads[1]]), head);
+            assert_eq!(self::head(&[&[line, reads[0]].concat(), reads[1]]), head);
+        }
+        assert_eq!(
+            head(&[line, b"\r\n", b"\r", b"\n"]),
+            [line, b"\r\n\r\n"].concat()
+        );
+    }
+
+    #[test]
+    fn head_size_limit() {
+        let cfg = config(&["--max-head-size", "100"]);
+        for len in [99, 100, 101] {
+            let head = head_of(len);
+            assert_eq!(head.len(), len);
+            for res in [
+                verify(&[&head], &cfg),
+                verify(&[&head[..50], &head[50..]], &cfg),
+                verify(&head.chunks(1).collect::<Vec<_>>(), &cfg),
+            ] {
+                match len {
+                    101 => assert_eq!(status(&res), Some("431 Request Header Fields Too Large")),
+                    _ => assert!(res.is_ok(), "{}", len),
+                }
+            }
+        }
+
+        // an endless head is cut off at the limit
+        let res = verify(&[&[b'a'; 1000]], &cfg);
+        assert_eq!(status(&res), Some("431 Request Header Fields Too Large"));
+    }
+
+    #[test]
+    fn missing_agent() {
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        let empty = b"GET / HTTP/1.1\r\nUser-Agent: \r\nHost: x\r\n\r\n";
+        for reads in [&[&head[..]], &[&empty[..]]] {
+            let res = verify(reads, &config(&[]));
+            assert_eq!(status(&res), Some("406 Not Acceptable"));
+
+            // unless the policy allows just about anyone
+            assert!(verify(reads, &config(&["--allow-agent", ""])).is_ok());
+        }
+    }
+
+    #[test]
+    fn too_many_headers() {
+        let cfg = config(&["--max-headers", "2"]);
+        let res = verify(
+            &[b"GET / HTTP/1.1\r\nUser-Agent: curl/8.5.0\r\nHost: x\r\n\r\n"],
+            &cfg,
+        );
+        assert!(res.is_ok());
+        let res = verify(
+            &[b"GET / HTTP/1.1\r\nUser-Agent: curl/8.5.0\r\nHost: x\r\nAccept: */*\r\n\r\n"],
+            &cfg,
+        );
+        assert_eq!(status(&res), Some("431 Request Header Fields Too Large"));
+    }
+
+    #[test]
+    fn end_of_stream_mid_head() {
+        let cfg = config(&[]);
+        for reads in [
+            &[][..],
+            &[&b"GET / HTTP/1.1\r\nUser-Agent: curl/8.5.0\r\n"[..]],
+            &[b"GET / HTTP/1.1\r\n", b"User-Agent: curl/8.5.0\r\n\r"],
+            &[&HEAD[..HEAD.len() - 1]],
+        ] {
+            assert_eq!(status(&verify(reads, &cfg)), Some("400 Bad Request"));
+        }
+    }
+
+    quickcheck! {
+        /// Garbage is refused in whatever reads it arrives, without ever panicking.
+        fn garbage_never_panics(reads: Vec<Vec<u8>>) -> bool {
+            let reads = reads.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            _ = verify(&reads, &config(&[]));
+            true
+        }
+
+        /// Garbage within a head that otherwise looks like a request never panics either.
+        fn garbage_head_never_panics(line: Vec<u8>, headers: Vec<u8>) -> bool {
+            let head = [b"GET ", &line[..], b" HTTP/1.1\r\n", &headers[..], b"\r\n\r\n"].concat();
+            _ = verify(&[&head], &config(&[]));
+            true
+        }
+
+        /// The head always ends at the first empty line after the offset, if there is one.
+        fn head_ends_at_empty_line(buf: Vec<u8>, from: usize) -> bool {
+            let from = from % (buf.len() + 1);
+            // an empty line ending at the given length, whose line feed is at or after the offset
+            let empty_line = |len: usize| {
+                (len >= from + 2 && buf[..len].ends_with(b"\n\n"))
+                    || (len >= from + 3 && buf[..len].ends_with(b"\n\r\n"))
+            };
+            match head_len(&buf, from) {
+                Some(len) => len <= buf.len() && empty_line(len) && !(from..len).any(empty_line),
+                None => !(from..=buf.len()).any(empty_line),
+            }
+        }
+
+        /// A request is read the same regardless of where the reads split it.
+        fn splits_make_no_difference(cuts: Vec<usize>) -> bool {
+            let mut cuts = cuts.iter().map(|c| c % HEAD.len()).chain([0, HEAD.len()]).collect::<Vec<_>>();
+            cuts.sort_unstable();
+            cuts.dedup();
+            let reads = cuts.windows(2).map(|w| &HEAD[w[0]..w[1]]).collect::<Vec<_>>();
+            verify(&reads, &config(&[])).is_ok()
+        }
+    }
 }
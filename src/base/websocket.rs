@@ -0,0 +1,540 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use httparse::Header;
+use log::debug;
+use parking_lot::Mutex;
+use sha1::{Digest, Sha1};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr},
+    sync::Arc,
+    thread::spawn,
+    time::Duration,
+};
+
+use super::{AsciiFrame, Playback, Queue, Step, Stream, UriError};
+
+/// Appended to the key of the client before it is hashed into the accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Opcodes of WebSocket frames
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Status codes of a closing handshake
+const NORMAL: u16 = 1000;
+const GOING_AWAY: u16 = 1001;
+const PROTOCOL_ERROR: u16 = 1002;
+
+/// Longest reason of a closing handshake, which has to fit in a control frame along with the status
+const MAX_REASON: usize = 123;
+
+/// Largest frame accepted from a client, which has no reason to send anything but control frames
+const MAX_PAYLOAD: u64 = 1 << 16;
+
+/// How long a client may stay silent before it is pinged, and how long it then has to answer
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How each frame of the animation is sent, according to the subprotocol the client asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A binary message of the delay in milliseconds, as a big-endian `u32`, followed by the frame
+    Binary,
+    /// A text message of a JSON object with the `index` and `delay` of the frame along with the `frame` itself
+    Json,
+}
+
+impl Format {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// A request to upgrade to a WebSocket (RFC 6455)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Upgrade {
+    accept: [u8; 28],
+    protocol: Option<Format>,
+}
+
+impl Upgrade {
+    /// The upgrade the headers of a request ask for, if any.
+    pub fn from_headers(headers: &[Header]) -> Result<Option<Self>, UriError> {
+        let value = |name: &str| {
+            headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| String::from_utf8_lossy(h.value).trim().to_owned())
+        };
+
+        if !value("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
+            return Ok(None);
+        }
+        // the `Connection` header lists `Upgrade` among its tokens, on whichever line
+        let is_upgrading = headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("Connection"))
+            .flat_map(|h| h.value.split(|b| *b == b','))
+            .any(|token| token.trim_ascii().eq_ignore_ascii_case(b"upgrade"));
+        if !is_upgrading {
+            return Err(UriError::Upgrade(
+                "the connection has to ask for it".to_owned(),
+            ));
+        }
+        if value("Sec-WebSocket-Version").as_deref() != Some("13") {
+            return Err(UriError::Upgrade("only version 13 is supported".to_owned()));
+        }
+        let Some(key) = value("Sec-WebSocket-Key") else {
+            return Err(UriError::Upgrade("the key is missing".to_owned()));
+        };
+        // the key is a random nonce of 16 bytes in base64
+        if STANDARD
+            .decode(&key)
+            .map_or(true, |nonce| nonce.len() != 16)
+        {
+            return Err(UriError::Upgrade("the key is invalid".to_owned()));
+        }
+
+        let mut accept = [0; 28];
+        STANDARD
+            .encode_slice(Sha1::digest(format!("{}{}", key, GUID)), &mut accept)
+            .map_err(|_| UriError::Upgrade("the key is invalid".to_owned()))?;
+
+        // the first subprotocol that is supported, if the client asked for any
+        let protocol = value("Sec-WebSocket-Protocol").and_then(|v| {
+            v.split(',').find_map(|p| match p.trim() {
+                "binary" => Some(Format::Binary),
+                "json" => Some(Format::Json),
+                _ => None,
+            })
+        });
+        Ok(Some(Self { accept, protocol }))
+    }
+
+    /// The response that completes the upgrade.
+    pub fn head(&self) -> String {
+        let protocol = self.protocol.map_or(String::new(), |p| {
+            format!("Sec-WebSocket-Protocol: {}\r\n", p.name())
+        });
+        format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\n\
+            {}\r\n",
+            String::from_utf8_lossy(&self.accept),
+            protocol
+        )
+    }
+
+    /// The format of every message, as negotiated through the subprotocol.
+    pub fn format(&self) -> Format {
+        self.protocol.unwrap_or(Format::Binary)
+    }
+}
+
+/// Reads the frames of a client, keeping whatever arrived of the next frame across reads that time out
+struct FrameReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    /// Whether a fragmented message is yet to be finished
+    is_fragmented: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            is_fragmented: false,
+        }
+    }
+
+    /// Read the next frame, returning its opcode and unmasked payload.
+    fn read(&mut self) -> std::io::Result<(u8, Vec<u8>)> {
+        let mut chunk = [0; 1024];
+        loop {
+            if let Some(frame) = self.parse()? {
+                return Ok(frame);
+            }
+            match self.inner.read(&mut chunk)? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    /// Take the first frame out of the buffer, if it arrived in full.
+    fn parse(&mut self) -> std::io::Result<Option<(u8, Vec<u8>)>> {
+        let invalid = |msg| Err(std::io::Error::new(ErrorKind::InvalidData, msg));
+        let [head, second, ..] = self.buf[..] else {
+            return Ok(None);
+        };
+        let (is_fin, opcode) = (head & 0x80 != 0, head & 0x0F);
+
+        // no extension was negotiated, so the reserved bits stay unset
+        if head & 0x70 != 0 {
+            return invalid("reserved bits set");
+        }
+        // every frame of a client is masked
+        if second & 0x80 == 0 {
+            return invalid("unmasked frame");
+        }
+
+        let (len, offset) = match second & 0x7F {
+            126 => match self.buf.get(2..4) {
+                Some(len) => (u64::from(u16::from_be_bytes([len[0], len[1]])), 4),
+                None => return Ok(None),
+            },
+            127 => match self.buf.get(2..10) {
+                Some(len) => (u64::from_be_bytes(len.try_into().unwrap()), 10),
+                None => return Ok(None),
+            },
+            len => (u64::from(len), 2),
+        };
+        if len > MAX_PAYLOAD {
+            return invalid("frame too big");
+        }
+
+        // control frames may come in between the fragments of a message, but are never fragmented themselves
+        match opcode {
+            CLOSE | PING | PONG if !is_fin || len > 125 => return invalid("invalid control frame"),
+            CLOSE | PING | PONG => {}
+            CONTINUATION if !self.is_fragmented => return invalid("unexpected continuation"),
+            TEXT | BINARY if self.is_fragmented => return invalid("unfinished message"),
+            CONTINUATION | TEXT | BINARY => {}
+            _ => return invalid("unknown opcode"),
+        }
+
+        let end = offset + 4 + len as usize;
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+        if matches!(opcode, CONTINUATION | TEXT | BINARY) {
+            self.is_fragmented = !is_fin
+        }
+
+        let mask = [
+            self.buf[offset],
+            self.buf[offset + 1],
+            self.buf[offset + 2],
+            self.buf[offset + 3],
+        ];
+        let mut payload = self.buf.drain(..end).skip(offset + 4).collect::<Vec<_>>();
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+        Ok(Some((opcode, payload)))
+    }
+}
+
+/// A WebSocket connection, where the writer of the frames and the reader answering the client take turns sending
+pub struct WebSocket {
+    stream: Stream,
+    format: Format,
+    lock: Mutex<()>,
+}
+
+impl WebSocket {
+    pub fn new(stream: Stream, upgrade: Upgrade) -> Self {
+        Self {
+            stream,
+            format: upgrade.format(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Send a single, unfragmented frame.
+    fn send(&self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ ..=125 => frame.push(len as u8),
+            len @ ..=0xFFFF => {
+                frame.push(126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend((len as u64).to_be_bytes());
+            }
+        }
+        frame.extend(payload);
+
+        let _guard = self.lock.lock();
+        (&self.stream).write_all(&frame)
+    }
+
+    /// Send the frame of the animation at the given index as a message.
+    fn send_frame(&self, index: usize, frame: &AsciiFrame, colored: bool) -> std::io::Result<()> {
+        match self.format {
+            Format::Binary => {
+                let delay = frame.delay_millis().to_be_bytes();
+                self.send(BINARY, &[&delay, &frame.standalone(colored)[..]].concat())
+            }
+            Format::Json => self.send(TEXT, frame.to_json(index, colored).as_bytes()),
+        }
+    }
+
+    /// Begin the closing handshake with the given status and reason, which is cut short if needed.
+    fn close(&self, status: u16, reason: &str) -> std::io::Result<()> {
+        let mut len = reason.len().min(MAX_REASON);
+        while !reason.is_char_boundary(len) {
+            len -= 1
+        }
+        self.send(
+            CLOSE,
+            &[&status.to_be_bytes(), &reason.as_bytes()[..len]].concat(),
+        )
+    }
+
+    /// Answer the pings of the client on a thread of its own and ping it in turn whenever it is silent,
+    /// shutting the connection down once it stops answering, which also ends the writer.
+    pub fn keep_alive(self: Arc<Self>, reader: Stream, addr: SocketAddr) {
+        spawn(move || {
+            let mut pinged = false;
+            if reader.set_read_timeout(Some(PING_INTERVAL)).is_err() {
+                return;
+            }
+            let mut frames = FrameReader::new(&reader);
+            loop {
+                let res = match frames.read() {
+                    Ok((PING, payload)) => self.send(PONG, &payload),
+                    Ok((CLOSE, payload)) => {
+                        // echo the status, unless the close was ours to begin with
+                        _ = self.send(CLOSE, &payload[..payload.len().min(2)]);
+                        break;
+                    }
+                    // anything the client sends shows that it is still there
+                    Ok(_) => {
+                        pinged = false;
+                        Ok(())
+                    }
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        if pinged {
+                            debug!("Unresponsive {}: no pong within {:?}", addr, PING_INTERVAL);
+                            break;
+                        }
+                        pinged = true;
+                        self.send(PING, &[])
+                    }
+                    Err(e) if e.kind() == ErrorKind::InvalidData => {
+                        _ = self.close(PROTOCOL_ERROR, "");
+                        break;
+                    }
+                    Err(_) => break,
+                };
+                if res.is_err() {
+                    break;
+                }
+            }
+            _ = reader.shutdown(Shutdown::Both);
+        });
+    }
+}
+
+/// Send every queued frame as a message according to the playback, until either the stream or the queue closes.
+pub fn write_messages(
+    socket: &WebSocket,
+    frames: &[AsciiFrame],
+    mut playback: Playback,
+    queue: &Queue<usize>,
+) -> std::io::Result<()> {
+    while let Some(i) = queue.pop() {
+        let (index, frame) = match playback.advance(i) {
+            Step::Frame(j) => (j, &frames[j]),
+            Step::Skip => continue,
+            Step::End => {
+                return match playback.farewell() {
+                    Some(goodbye) => socket.close(GOING_AWAY, goodbye),
+                    None => socket.close(NORMAL, ""),
+                }
+            }
+        };
+        socket.send_frame(index, frame, playback.is_colored())?
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Key and accept key of the example handshake of RFC 6455
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+    /// A stream handing out the given reads one after another, where `None` stands for a read that times out.
+    struct Reads(VecDeque<Option<Vec<u8>>>);
+
+    impl Read for Reads {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Some(read)) => {
+                    buf[..read.len()].copy_from_slice(&read);
+                    Ok(read.len())
+                }
+                Some(None) => Err(ErrorKind::WouldBlock.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    /// A frame of the client with the given first byte, masked by the given key.
+    fn masked(head: u8, mask: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![head];
+        match payload.len() {
+            len @ ..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+        }
+        frame.extend(mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn reader(reads: &[Option<&[u8]>]) -> FrameReader<Reads> {
+        FrameReader::new(Reads(reads.iter().map(|r| r.map(<[u8]>::to_vec)).collect()))
+    }
+
+    fn upgrade(headers: &[(&'static str, &'static str)]) -> Result<Option<Upgrade>, UriError> {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| Header {
+                name,
+                value: value.as_bytes(),
+            })
+            .collect::<Vec<_>>();
+        Upgrade::from_headers(&headers)
+    }
+
+    const HANDSHAKE: [(&str, &str); 4] = [
+        ("Upgrade", "websocket"),
+        ("Connection", "keep-alive, Upgrade"),
+        ("Sec-WebSocket-Version", "13"),
+        ("Sec-WebSocket-Key", KEY),
+    ];
+
+    #[test]
+    fn accept_key() {
+        let accepted = upgrade(&HANDSHAKE).ok().flatten().unwrap();
+        assert!(accepted
+            .head()
+            .contains(&format!("Sec-WebSocket-Accept: {}\r\n", ACCEPT)));
+        assert_eq!(accepted.format(), Format::Binary);
+
+        let mut headers = HANDSHAKE.to_vec();
+        headers.push(("Sec-WebSocket-Protocol", "chat, json, binary"));
+        let accepted = upgrade(&headers).ok().flatten().unwrap();
+        assert_eq!(accepted.format(), Format::Json);
+    }
+
+    #[test]
+    fn rejected_handshakes() {
+        // requests that don't ask for a WebSocket at all are streamed as usual
+        assert!(matches!(upgrade(&HANDSHAKE[1..]), Ok(None)));
+        assert!(matches!(upgrade(&[("Upgrade", "h2c")]), Ok(None)));
+
+        for (i, value) in [
+            (1, "keep-alive"),
+            (2, "8"),
+            (3, "not base64"),
+            (3, "c2hvcnQ="),
+        ] {
+            let mut headers = HANDSHAKE.to_vec();
+            headers[i].1 = value;
+            assert!(upgrade(&headers).is_err(), "{}", value);
+
+            // missing headers are just as bad
+            headers.remove(i);
+            assert!(upgrade(&headers).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn unmasking() {
+        // the masked "Hello" of RFC 6455
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            reader(&[Some(&frame)]).read().unwrap(),
+            (TEXT, b"Hello".to_vec())
+        );
+
+        // whereas an unmasked one is refused
+        let res = reader(&[Some(&[0x81, 0x05, b'H', b'e', b'l', b'l', b'o'])]).read();
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn frame_across_timeouts() {
+        let frame = masked(0x89, [1, 2, 3, 4], b"ping");
+        let mut frames = reader(&[
+            Some(&frame[..3]),
+            None,
+            Some(&frame[3..7]),
+            None,
+            Some(&frame[7..]),
+        ]);
+        for _ in 0..2 {
+            assert_eq!(frames.read().unwrap_err().kind(), ErrorKind::WouldBlock);
+        }
+        assert_eq!(frames.read().unwrap(), (PING, b"ping".to_vec()));
+        assert_eq!(frames.read().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn fragments() {
+        // a ping may come in between the fragments of a message
+        let reads = [
+            masked(0x01, [9; 4], b"Hel"),
+            masked(0x89, [8; 4], b""),
+            masked(0x80, [7; 4], b"lo"),
+            masked(0x88, [6; 4], &NORMAL.to_be_bytes()),
+        ];
+        let mut frames = reader(&[Some(&reads.concat())]);
+        for frame in [
+            (TEXT, b"Hel".to_vec()),
+            (PING, vec![]),
+            (CONTINUATION, b"lo".to_vec()),
+            (CLOSE, NORMAL.to_be_bytes().to_vec()),
+        ] {
+            assert_eq!(frames.read().unwrap(), frame);
+        }
+
+        for reads in [
+            // a continuation of nothing
+            vec![masked(0x80, [0; 4], b"lo")],
+            // a new message before the last one is finished
+            vec![masked(0x01, [0; 4], b"Hel"), masked(0x81, [0; 4], b"lo")],
+            // a fragmented control frame
+            vec![masked(0x08, [0; 4], &NORMAL.to_be_bytes())],
+            // a control frame that is too long
+            vec![masked(0x89, [0; 4], &[0; 126])],
+            // a reserved bit
+            vec![masked(0xC1, [0; 4], b"Hello")],
+        ] {
+            let mut frames = reader(&[Some(&reads.concat())]);
+            let res = (0..reads.len()).try_for_each(|_| frames.read().map(drop));
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut head = vec![0x82, 0xFF];
+        head.extend((MAX_PAYLOAD + 1).to_be_bytes());
+        let res = reader(&[Some(&head)]).read();
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
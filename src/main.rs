@@ -1,44 +1,171 @@
 mod base;
 
-use log::trace;
-use std::net::TcpListener;
+use log::{info, trace};
+use std::sync::Arc;
 
 use base::*;
 
 fn main() -> Result {
     // parse program arguments
-    let cfg = Config::new()?;
+    let cfg = Arc::new(Config::new()?);
+
+    // take over the sockets systemd listens on, before anything else could
+    let mut activation = Activation::from_env()?;
+    let notifier = Notifier::from_env()?;
 
     // create log file if it does not already exist
     #[cfg(feature = "logger")]
     init_log_file();
 
-    // retrieve ascii frames
-    let frames = get_frames(&cfg)?;
+    // addresses allowed to connect, which are reloaded on SIGHUP
+    let access = Arc::new(Access::new(&cfg)?);
+    let reloaded = access.clone();
+    reload_handler("access list", move || reloaded.reload())?;
+
+    // connection limits shared by every listener, which count the handshakes in progress on any of them as well
+    let limiter = Arc::new(Limiter::new(&cfg));
+
+    // every server along with how it talks to its clients, and the listener whose routes it serves if not all of them
+    let mut bound = Vec::new();
+
+    // stream to raw clients on a port of their own
+    if let Some(addr) = cfg.raw_addr() {
+        trace!("Initializing raw TCP server");
+        bound.push((Server::Tcp(activation.bind(addr)?), Protocol::Raw, None));
+        trace!("Streaming @ {}", addr);
+    }
 
-    // init listener
+    // stream to telnet clients on a port of their own
+    if let Some(addr) = cfg.telnet_addr() {
+        trace!("Initializing telnet server");
+        bound.push((Server::Tcp(activation.bind(addr)?), Protocol::Telnet, None));
+        trace!("Streaming @ telnet://{}", addr);
+    }
+
+    // every listener feeds the same channels
     trace!("Initializing TCP server");
-    let server = TcpListener::bind(cfg.addr())?;
+    for listener in cfg.listeners() {
+        let server = Server::Tcp(activation.bind(listener.addr())?);
+        bound.push((server, Protocol::Http, Some(listener)));
+    }
+
+    // terminate TLS in-process, where the certificate is reloaded on SIGHUP
+    #[cfg(feature = "tls")]
+    if let Some((addr, cert, key)) = cfg.tls() {
+        let cert = Arc::new(Certificate::new(cert, key)?);
+        let reloaded = cert.clone();
+        reload_handler("certificate", move || reloaded.reload())?;
+
+        bound.push((
+            Server::Tls(activation.bind(addr)?, cert),
+            Protocol::Http,
+            None,
+        ));
+        trace!("Listening @ https://{}", addr);
+    }
+
+    // stream to SSH clients, which authenticate the server by its host key
+    #[cfg(feature = "ssh")]
+    if let Some((addr, key)) = cfg.ssh() {
+        let server = Server::Ssh(activation.bind(addr)?, Arc::new(HostKey::new(key)?));
+        bound.push((server, Protocol::Ssh, None));
+        trace!("Streaming @ ssh://{}", addr);
+    }
+
+    // serve reverse proxies on a Unix domain socket as well
+    #[cfg(unix)]
+    if let Some(path) = cfg.unix_socket() {
+        let server = activation.bind_unix(path, cfg.unix_socket_mode())?;
+        bound.push((server, Protocol::Http, None));
+        trace!("Listening @ unix:{}", path.display());
+    }
+
+    // sockets that systemd listens on without any address being configured for them serve every route
+    for server in activation.into_servers() {
+        bound.push((server, Protocol::Http, None));
+    }
+
+    // every port is bound, so the privileges it took are dropped before any untrusted GIF is decoded
+    #[cfg(unix)]
+    drop_privileges(&cfg)?;
+
+    // retrieve the ascii frames of every animation
+    let channels = cfg
+        .routes()
+        .iter()
+        .map(|route| {
+            Ok(Arc::new(Channel::new(
+                route.path(),
+                get_frames(route, &cfg)?,
+            )))
+        })
+        .collect::<Result<Arc<[_]>>>()?;
+
+    // every thread that stops once the server shuts down, along with every listener that has to be woken up for it
+    let shutdown = Shutdown::default();
+    let mut handles = Vec::new();
+    let mut servers = Vec::new();
 
-    // connected clients
-    let streams = SignalLock::default();
+    for channel in channels.iter() {
+        // init handlers
+        handles.push(error_handler(
+            &shutdown,
+            channel.streams().clone(),
+            channel.disconnected().clone(),
+        ));
 
-    // disconnected clients
-    let disconnected = SignalLock::default();
+        // playback position of the channel
+        let mut scheduler = Scheduler::default();
 
-    // init handlers
-    error_handler(streams.clone(), disconnected.clone());
-    incoming_handler(server, streams.clone(), cfg.path().to_owned());
+        // Distribute frames to each client as long as there is at least one connection.
+        // Otherwise, the thread remains paused.
+        let channel = channel.clone();
+        let stopped = shutdown.clone();
+        handles.push(init_handler(&shutdown, move || {
+            // the scheduler keeps track of the position between iterations
+            dist_handler(
+                &stopped,
+                channel.streams(),
+                channel.disconnected(),
+                channel.frames(),
+                &mut scheduler,
+                channel.heartbeat(),
+            )
+        }));
+    }
 
-    trace!("Listening @ http://{}{}\n", cfg.addr(), cfg.path());
+    // accept clients on every server, now that their channels are ready
+    for (server, protocol, listener) in bound {
+        let mounts = match listener {
+            Some(listener) => {
+                let mounts = Mount::listener(listener, &channels);
+                for mount in mounts.iter() {
+                    trace!("Listening @ http://{}{}", listener.addr(), mount.path());
+                }
+                mounts
+            }
+            None => Mount::all(&channels),
+        };
+        servers.push(server.try_clone()?);
+        handles.push(incoming_handler(
+            server,
+            mounts,
+            cfg.clone(),
+            access.clone(),
+            limiter.clone(),
+            protocol,
+            &shutdown,
+        ));
+    }
 
-    // global frame index
-    let mut frame_index = 0;
+    // the frames are ready and every listener is up, so systemd can consider the service started
+    notifier.notify("READY=1")?;
+    watchdog_handler(channels.clone(), notifier.clone(), &shutdown);
 
-    // Distribute frames to each client as long as there is at least one connection.
-    // Otherwise, the thread remains paused.
-    loop_func(move || {
-        // update frame index after every iteration
-        dist_handler(&streams, &disconnected, &frames, &mut frame_index)
-    })
+    // verify and accept clients until the server is asked to shut down
+    shutdown_handler()?;
+    info!("Shutting down");
+    notifier.notify("STOPPING=1")?;
+    shut_down(&shutdown, &servers, &channels, handles, &cfg);
+    Ok(())
 }